@@ -14,14 +14,94 @@ export function errorPopup(msg: string) {
     return invoke()<null>("error_popup", { msg })
 }
 
-export function loadBackupList(destinationId: string, refresh: boolean) {
-    return invoke()<Backup[]>("load_backup_list", { destinationId,refresh })
+export function loadBackupList(destinationId: string, refresh: boolean, estimateSizes: boolean) {
+    return invoke()<Backup[]>("load_backup_list", { destinationId,refresh,estimateSizes })
 }
 
 export function getBackup(destinationId: string, newB: string, refresh: boolean) {
     return invoke()<DirMap>("get_backup", { destinationId,newB,refresh })
 }
 
+export function compareBackups(destinationId: string, oldB: string, newB: string, refresh: boolean) {
+    return invoke()<DirMap>("compare_backups", { destinationId,oldB,newB,refresh })
+}
+
+export function cancelBackup(oldB: string, newB: string) {
+    return invoke()<null>("cancel_backup", { oldB,newB })
+}
+
+export function closeDestination(destinationId: string) {
+    return invoke()<null>("close_destination", { destinationId })
+}
+
+export function clearCache() {
+    return invoke()<null>("clear_cache")
+}
+
+export function backupSizes(destinationId: string) {
+    return invoke()<BackupSize[]>("backup_sizes", { destinationId })
+}
+
+export function cancelSizeEstimates(destinationId: string) {
+    return invoke()<null>("cancel_size_estimates", { destinationId })
+}
+
+export function growthTimeline(destinationId: string) {
+    return invoke()<Timeline>("growth_timeline", { destinationId })
+}
+
+export function pathHistory(destinationId: string, path: string, fromB: string, toB: string) {
+    return invoke()<PathHistory>("path_history", { destinationId,path,fromB,toB })
+}
+
+export function churnReport(destinationId: string, fromB: string, toB: string, minFrequency: number, limit: number) {
+    return invoke()<ChurnReport>("churn_report", { destinationId,fromB,toB,minFrequency,limit })
+}
+
+export function pathVersions(destinationId: string, path: string) {
+    return invoke()<PathVersions>("path_versions", { destinationId,path })
+}
+
+export function cancelAnalysis() {
+    return invoke()<null>("cancel_analysis")
+}
+
+export function exclusionSuggestions(destinationId: string, oldB: string, newB: string, userRules: ExclusionRule[]) {
+    return invoke()<Suggestion[]>("exclusion_suggestions", { destinationId,oldB,newB,userRules })
+}
+
+export function addExclusion(destinationId: string, path: string, fromB: string, toB: string, dryRun: boolean) {
+    return invoke()<ExclusionResult>("add_exclusion", { destinationId,path,fromB,toB,dryRun })
+}
+
+export function comparisonExclusions(destinationId: string, oldB: string, newB: string) {
+    return invoke()<ComparisonExclusions>("comparison_exclusions", { destinationId,oldB,newB })
+}
+
+export function exportComparison(destinationId: string, oldB: string, newB: string, format: ExportFormat) {
+    return invoke()<string | null>("export_comparison", { destinationId,oldB,newB,format })
+}
+
+export function topItems(destinationId: string, oldB: string, newB: string, filter: TopFilter, limit: number) {
+    return invoke()<TopItems>("top_items", { destinationId,oldB,newB,filter,limit })
+}
+
+export function searchComparison(destinationId: string, oldB: string, newB: string, query: SearchQuery, limit: number) {
+    return invoke()<SearchResults>("search_comparison", { destinationId,oldB,newB,query,limit })
+}
+
+export function restorePlan(source: string) {
+    return invoke()<RestorePlan | null>("restore_plan", { source })
+}
+
+export function restoreItem(source: string, destinationDir: string, overwrite: boolean) {
+    return invoke()<null>("restore_item", { source,destinationDir,overwrite })
+}
+
+export function cancelRestore() {
+    return invoke()<null>("cancel_restore")
+}
+
 export function backupsInfo() {
     return invoke()<BackupInfo[]>("backups_info")
 }
@@ -30,8 +110,41 @@ export function destinationinfo() {
     return invoke()<DestinationDetail[]>("destinationinfo")
 }
 
-export type Backup = { path: string; name: string }
-export type LoadedBackupItem = { size: number }
+export type Backup = { path: string; name: string; timestamp: number | null; interval: number | null }
+export type LoadedBackupItem = { size: number; added_size: number; updated_size: number; removed_size: number; added_count: number; updated_count: number; removed_count: number; change: ItemChange | null }
 export type DestinationDetail = { id: string; mount_point: string; mount_point_name: string }
-export type DirMap = { map: { [key: string]: { [key: string]: LoadedBackupItem } } }
-export type BackupInfo = { old: string; new: string; loading: boolean }
+export type DirMap = { map: { [key: string]: { [key: string]: LoadedBackupItem } }; totals: Totals }
+export type BackupInfo = { old: string; new: string; loading: boolean; progress: CompareProgress | null }
+export type CompareProgress = { old: string; new: string; bytes_read: number; changes: number; current_path: string; elapsed_ms: number }
+export type ItemChange = { kind: ChangeKind; old_size: number | null; new_size: number | null; differences: Difference[]; raw_differences: string[] }
+export type ChangeKind = "Added" | "Updated" | "Removed"
+export type Difference = "Content" | "Size" | "Permissions" | "Ownership" | "Dates" | "ExtendedAttributes" | "Flags" | "Metadata" | "Other"
+export type Totals = { added_size: number; changed_size: number; removed_size: number }
+export type Error = (ErrorKind) & { message: string }
+export type ErrorKind = { kind: "Tmutil"; code: number | null; stderr: string } | { kind: "FullDiskAccess" } | { kind: "PermissionDenied"; path: string } | { kind: "Io"; path: string } | { kind: "Parse"; value: string | null } | { kind: "DestinationsNotLoaded" } | { kind: "DestinationNotFound"; id: string } | { kind: "BackupListNotLoaded" } | { kind: "BackupNotFound"; path: string } | { kind: "NoPreviousBackup" } | { kind: "BackupOrder"; old: string; new: string } | { kind: "AlreadyLoading" } | { kind: "AlreadyExists"; path: string } | { kind: "Cancelled" } | { kind: "Other" }
+export type BackupSize = { destination_id: string; old: string; new: string; totals: Totals }
+export type Timeline = { points: TimelinePoint[]; max_size: number; missing: number }
+export type TimelinePoint = { path: string; name: string; timestamp: number | null; totals: Totals | null; running_total: number }
+export type PathHistory = { path: string; points: PathHistoryPoint[]; changed_count: number; total_size: number }
+export type PathHistoryPoint = { backup: string; name: string; timestamp: number | null; item: LoadedBackupItem }
+export type PathVersions = { path: string; versions: PathVersion[]; unchecked: string[] }
+export type PathVersion = { backup: string; name: string; timestamp: number | null; kind: ChangeKind; old_size: number | null; new_size: number | null; from_comparison: boolean }
+export type ChurnReport = { comparisons: number; churners: Churner[] }
+export type Churner = { path: string; is_file: boolean; count: number; frequency: number; total_size: number; max_size: number }
+export type ExclusionRule = { name: string; pattern: string }
+export type Suggestion = { path: string; source_path: string; rule: string; is_file: boolean; size: number; excluded: boolean }
+export type ExclusionResult = { source_path: string; saved: PathHistory; excluded: boolean }
+export type ComparisonExclusions = { exclusions: Exclusion[]; excluded_paths: string[] }
+export type Exclusion = { path: string; source: ExclusionSource }
+export type ExclusionSource = "Preferences" | "Sticky"
+export type ExportFormat = "Csv" | "Json" | "Ncdu" | "Html"
+export type TopFilter = { prefix: string | null; extensions: string[] }
+export type TopEntry = { path: string; size: number; count: number; kind: ChangeKind | null }
+export type SearchMode = "Substring" | "Glob" | "Regex"
+export type SearchQuery = { pattern: string; mode: SearchMode; case_sensitive: boolean; kinds: ChangeKind[]; min_size: number }
+export type SearchMatch = { path: string; is_dir: boolean; item: LoadedBackupItem }
+export type SearchResults = { matches: SearchMatch[]; ancestors: string[]; truncated: boolean }
+export type RestorePlan = { source: string; destination: string; exists: boolean; entries: RestoreEntry[]; file_count: number; total_size: number }
+export type RestoreEntry = { path: string; is_dir: boolean; size: number }
+export type RestoreProgress = { done: number; total: number; current_path: string }
+export type TopItems = { files: TopEntry[]; dirs_by_own_size: TopEntry[]; dirs_by_subtree_size: TopEntry[] }
@@ -0,0 +1,229 @@
+use crate::cmd::{self, CacheState, DestinationsState, LoadedBackups};
+use crate::sizes::SizeEstimates;
+use serde::Serialize;
+use specta::Type;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use tauri::{command, Manager, State, Window};
+use time_machine_inspector::compare::CancelToken;
+use time_machine_inspector::dir_map::DirMap;
+use time_machine_inspector::error::Error;
+use time_machine_inspector::history::{
+	self, ChurnAnalysis, ChurnReport, PathHistory, PathVersions, Timeline,
+};
+use time_machine_inspector::listbackups::Backup;
+
+pub const ANALYSIS_PROGRESS_EVENT: &str = "analysis-progress";
+
+#[derive(Serialize, Clone, Type)]
+pub struct AnalysisProgress {
+	/// Comparisons done so far
+	pub done: u32,
+	pub total: u32,
+}
+
+/// The analysis that's running, if any. Starting one cancels the previous
+/// one.
+#[derive(Default)]
+pub struct RunningAnalysis(Mutex<Option<CancelToken>>);
+
+impl RunningAnalysis {
+	fn lock(&self) -> Result<MutexGuard<Option<CancelToken>>, Error> {
+		match self.0.lock() {
+			Ok(mutex) => Ok(mutex),
+			Err(e) => Err(Error::other(format!("Unable to lock analysis: {}", e))),
+		}
+	}
+	pub fn start(&self) -> Result<CancelToken, Error> {
+		let cancel = CancelToken::new();
+		if let Some(previous) = self.lock()?.replace(cancel.clone()) {
+			previous.cancel();
+		}
+		Ok(cancel)
+	}
+	pub fn cancel(&self) -> Result<(), Error> {
+		if let Some(cancel) = self.lock()?.take() {
+			cancel.cancel();
+		}
+		Ok(())
+	}
+}
+
+pub fn get_backups(
+	destinations_state: &DestinationsState,
+	destination_id: &str,
+) -> Result<Vec<Backup>, Error> {
+	let mut destinations = destinations_state.lock()?;
+	let destination = destinations.get_destination(destination_id)?;
+	Ok(destination.backups()?.clone())
+}
+
+/// Runs `f` with the comparison of each pair of adjacent backups, oldest
+/// first, emitting `ANALYSIS_PROGRESS_EVENT` after each one
+pub async fn for_each_comparison(
+	destination_id: &str,
+	backups: &[Backup],
+	w: Window,
+	running: &RunningAnalysis,
+	mut f: impl FnMut(&Backup, &DirMap),
+) -> Result<(), Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	let cancel = running.start()?;
+	let app = w.app_handle();
+	let total = backups.len().saturating_sub(1) as u32;
+	for (i, pair) in backups.windows(2).enumerate() {
+		let (old, new) = (&pair[0], &pair[1]);
+		cmd::with_comparison(
+			&app,
+			destination_id,
+			&old.path,
+			&new.path,
+			&cancel,
+			|dir_map| f(new, dir_map),
+		)?;
+		if cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		let progress = AnalysisProgress {
+			done: i as u32 + 1,
+			total,
+		};
+		if let Err(e) = w.emit(ANALYSIS_PROGRESS_EVENT, progress) {
+			eprintln!("Unable to emit analysis progress: {}", e);
+		}
+	}
+	Ok(())
+}
+
+/// How much each backup of a destination added, using the comparisons that
+/// are loaded, estimated or cached. Missing comparisons aren't run.
+#[command]
+#[specta::specta]
+pub async fn growth_timeline(
+	destination_id: String,
+	destinations_state: State<'_, DestinationsState>,
+	loaded_backups: State<'_, LoadedBackups>,
+	size_estimates: State<'_, SizeEstimates>,
+	cache: State<'_, CacheState>,
+) -> Result<Timeline, Error> {
+	let backups = get_backups(&destinations_state, &destination_id)?;
+	let mut known = HashMap::new();
+	for size in size_estimates.sizes(&destination_id)? {
+		known.insert((size.old, size.new), size.totals);
+	}
+	for (old_new, loaded_backup) in loaded_backups.lock()?.iter() {
+		if !loaded_backup.loading {
+			known.insert(old_new.clone(), loaded_backup.map.totals.clone());
+		}
+	}
+	Ok(history::timeline(&backups, |old, new| {
+		let old_new = (old.path.clone(), new.path.clone());
+		if let Some(totals) = known.remove(&old_new) {
+			return Some(totals);
+		}
+		let cache = cache.0.as_ref()?;
+		cache.load_totals(&destination_id, &old.path, &new.path)
+	}))
+}
+
+/// Changes at or below `path` in each backup from `from_b` to `to_b`. `path`
+/// is relative to the backups, like "Macintosh HD - Data/Users". Comparisons
+/// that aren't loaded or cached are run.
+#[command]
+#[specta::specta]
+pub async fn path_history(
+	destination_id: String,
+	path: String,
+	from_b: String,
+	to_b: String,
+	w: Window,
+	destinations_state: State<'_, DestinationsState>,
+	running: State<'_, RunningAnalysis>,
+) -> Result<PathHistory, Error> {
+	let backups = get_backups(&destinations_state, &destination_id)?;
+	let range = history::backup_range(&backups, &from_b, &to_b)?;
+	let mut path_history = PathHistory::new(&path);
+	for_each_comparison(&destination_id, range, w, &running, |new, dir_map| {
+		path_history.add(new, dir_map)
+	})
+	.await?;
+	Ok(path_history)
+}
+
+/// Paths that changed in at least `min_frequency` of the backups from
+/// `from_b` to `to_b`, from 0 to 1. Comparisons that aren't loaded or cached
+/// are run.
+#[command]
+#[specta::specta]
+pub async fn churn_report(
+	destination_id: String,
+	from_b: String,
+	to_b: String,
+	min_frequency: f64,
+	limit: u32,
+	w: Window,
+	destinations_state: State<'_, DestinationsState>,
+	running: State<'_, RunningAnalysis>,
+) -> Result<ChurnReport, Error> {
+	let backups = get_backups(&destinations_state, &destination_id)?;
+	let range = history::backup_range(&backups, &from_b, &to_b)?;
+	let mut churn = ChurnAnalysis::new();
+	for_each_comparison(&destination_id, range, w, &running, |new, dir_map| {
+		churn.add(new, dir_map)
+	})
+	.await?;
+	Ok(churn.report(min_frequency, limit as usize))
+}
+
+/// The backups where `path` was added, updated or removed, oldest first.
+/// `path` is relative to the backups. Loaded and cached comparisons are used
+/// where available, and the backup directories otherwise, so no comparisons
+/// are run.
+#[command]
+#[specta::specta]
+pub async fn path_versions(
+	destination_id: String,
+	path: String,
+	w: Window,
+	destinations_state: State<'_, DestinationsState>,
+	running: State<'_, RunningAnalysis>,
+) -> Result<PathVersions, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	let backups = get_backups(&destinations_state, &destination_id)?;
+	let cancel = running.start()?;
+	let app = w.app_handle();
+	let mut versions = PathVersions::new(&path);
+	for (i, new) in backups.iter().enumerate() {
+		let old = i.checked_sub(1).map(|i| &backups[i]);
+		let found = match old {
+			Some(old) => cmd::with_cached_comparison(
+				&app,
+				&destination_id,
+				&old.path,
+				&new.path,
+				|dir_map| versions.add_comparison(new, dir_map),
+			)?,
+			None => None,
+		};
+		if found.is_none() && versions.add_directories(old, new).is_err() {
+			versions.add_unchecked(new);
+		}
+		if cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		let progress = AnalysisProgress {
+			done: i as u32 + 1,
+			total: backups.len() as u32,
+		};
+		if let Err(e) = w.emit(ANALYSIS_PROGRESS_EVENT, progress) {
+			eprintln!("Unable to emit analysis progress: {}", e);
+		}
+	}
+	Ok(versions)
+}
+
+#[command]
+#[specta::specta]
+pub async fn cancel_analysis(running: State<'_, RunningAnalysis>) -> Result<(), Error> {
+	running.cancel()
+}
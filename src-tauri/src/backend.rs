@@ -0,0 +1,569 @@
+use crate::compare::{parse_xml_stream, CancelToken, OnChange, Totals};
+use crate::error::Error;
+use crate::restore::copy_tree;
+use crate::snapshot::compare_snapshots;
+use plist::{Dictionary, Value};
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::SystemTime;
+
+pub fn parse_output(bytes: Vec<u8>) -> Result<String, Error> {
+	match String::from_utf8(bytes) {
+		Ok(s) => Ok(s),
+		Err(e) => Err(Error::parse_value(
+			format!("Unable to parse output: {}", e),
+			String::from_utf8_lossy(e.as_bytes()),
+		)),
+	}
+}
+
+pub fn check_cmd_success(status: &ExitStatus, stderr: Vec<u8>) -> Result<(), Error> {
+	if !status.success() {
+		let stderr = String::from_utf8_lossy(&stderr).to_string();
+		return Err(Error::tmutil(status.code(), stderr));
+	}
+	Ok(())
+}
+
+/// Everything the inspector needs from `tmutil`. Implementations return the
+/// raw output of each subcommand, and parsing is left to the caller, except
+/// for `compare` which streams parsed changes.
+pub trait TmutilBackend: Send + Sync {
+	/// Output of `tmutil destinationinfo -X`
+	fn destinationinfo(&self) -> Result<Vec<u8>, Error>;
+	/// Output of `tmutil listbackups -d <mount_point>`
+	fn listbackups(&self, mount_point: &str) -> Result<String, Error>;
+	/// Output of `tmutil machinedirectory`
+	fn machinedirectory(&self) -> Result<String, Error>;
+	/// Result of `tmutil compare -X -s <old> <new>`. Each change is passed to
+	/// `on_change` as it's read, and the totals are returned at the end.
+	/// Backends that don't read any output report 0 bytes read. Any process
+	/// that's spawned is handed to `cancel` so that it can be killed.
+	fn compare(
+		&self,
+		old: &str,
+		new: &str,
+		cancel: &CancelToken,
+		on_change: &mut OnChange,
+	) -> Result<Totals, Error>;
+	/// Runs `tmutil addexclusion <path>`, which excludes the path from
+	/// future backups until it's removed
+	fn addexclusion(&self, path: &str) -> Result<(), Error>;
+	/// Output of `tmutil isexcluded <paths>`
+	fn isexcluded(&self, paths: &[&str]) -> Result<String, Error>;
+	/// Runs `tmutil restore -v <source> <destination>`, copying `source` out
+	/// of a backup to `destination`, which must not exist. Each line of
+	/// output, normally a restored path, is passed to `on_path` as it's read.
+	fn restore(
+		&self,
+		source: &str,
+		destination: &str,
+		cancel: &CancelToken,
+		on_path: &mut dyn FnMut(&str),
+	) -> Result<(), Error>;
+}
+
+pub struct BackendState(pub Box<dyn TmutilBackend>);
+
+/// Picks the backend to use. Setting `TMI_FIXTURES` to a directory serves
+/// recorded output from it, and setting `TMI_SNAPSHOTS` to a directory of
+/// hard-linked snapshots compares those directly. Otherwise `tmutil` is used.
+pub fn from_env() -> BackendState {
+	if let Some(dir) = std::env::var_os("TMI_FIXTURES") {
+		eprintln!("Using tmutil fixtures from {}", dir.to_string_lossy());
+		return BackendState(Box::new(FixtureBackend::new(dir)));
+	}
+	if let Some(dir) = std::env::var_os("TMI_SNAPSHOTS") {
+		eprintln!("Using snapshots from {}", dir.to_string_lossy());
+		return BackendState(Box::new(SnapshotBackend::new(dir)));
+	}
+	BackendState(Box::new(Tmutil::new()))
+}
+
+/// Runs the real `tmutil`
+pub struct Tmutil {
+	program: PathBuf,
+}
+
+impl Tmutil {
+	pub fn new() -> Self {
+		Self::with_program("tmutil")
+	}
+	/// Runs `program` instead of `tmutil`
+	pub fn with_program(program: impl Into<PathBuf>) -> Self {
+		Self {
+			program: program.into(),
+		}
+	}
+	fn output(&self, args: &[&str]) -> Result<Output, Error> {
+		let output = match Command::new(&self.program).args(args).output() {
+			Ok(output) => output,
+			Err(e) => return Err(Error::io("Unable to run", &self.program, e)),
+		};
+		check_cmd_success(&output.status, output.stderr.clone())?;
+		Ok(output)
+	}
+}
+
+impl Default for Tmutil {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl TmutilBackend for Tmutil {
+	fn destinationinfo(&self) -> Result<Vec<u8>, Error> {
+		let output = self.output(&["destinationinfo", "-X"])?;
+		eprintln!("Success running destinationinfo");
+		Ok(output.stdout)
+	}
+
+	fn listbackups(&self, mount_point: &str) -> Result<String, Error> {
+		eprintln!("tmutil listbackups");
+		let output = self.output(&["listbackups", "-d", mount_point])?;
+		eprintln!("Success listing backups");
+		parse_output(output.stdout)
+	}
+
+	fn machinedirectory(&self) -> Result<String, Error> {
+		eprintln!("tmutil machinedirectory");
+		let output = self.output(&["machinedirectory"])?;
+		eprintln!("Success getting machinedirectory");
+		parse_output(output.stdout)
+	}
+
+	fn addexclusion(&self, path: &str) -> Result<(), Error> {
+		eprintln!("tmutil addexclusion '{}'", path);
+		self.output(&["addexclusion", path])?;
+		Ok(())
+	}
+
+	fn isexcluded(&self, paths: &[&str]) -> Result<String, Error> {
+		let mut args = vec!["isexcluded"];
+		args.extend_from_slice(paths);
+		let output = self.output(&args)?;
+		parse_output(output.stdout)
+	}
+
+	fn restore(
+		&self,
+		source: &str,
+		destination: &str,
+		cancel: &CancelToken,
+		on_path: &mut dyn FnMut(&str),
+	) -> Result<(), Error> {
+		eprintln!("tmutil restore -v '{}' '{}'", source, destination);
+		let spawned = Command::new(&self.program)
+			.arg("restore")
+			.arg("-v")
+			.arg(source)
+			.arg(destination)
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn();
+		let mut cmd = match spawned {
+			Ok(cmd) => cmd,
+			Err(e) => return Err(Error::io("Unable to run", &self.program, e)),
+		};
+		let stdout = match cmd.stdout.take() {
+			Some(stdout) => stdout,
+			None => return Err(Error::other("Unable to read tmutil output")),
+		};
+		cancel.set_child(cmd);
+		for line in BufReader::new(stdout).lines() {
+			match line {
+				Ok(line) if !line.trim().is_empty() => on_path(line.trim()),
+				Ok(_) => {}
+				Err(_) => break,
+			}
+		}
+		let cmd = match cancel.take_child() {
+			Some(cmd) => cmd,
+			None => return Err(Error::other("tmutil process went missing")),
+		};
+		let output = match cmd.wait_with_output() {
+			Ok(output) => output,
+			Err(e) => return Err(Error::io("Unable to wait on", &self.program, e)),
+		};
+		if cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		check_cmd_success(&output.status, output.stderr)
+	}
+
+	fn compare(
+		&self,
+		old: &str,
+		new: &str,
+		cancel: &CancelToken,
+		on_change: &mut OnChange,
+	) -> Result<Totals, Error> {
+		eprintln!("tmutil compare -X -s '{}' '{}'", old, new);
+		let spawned = Command::new(&self.program)
+			.arg("compare")
+			.arg("-X")
+			.arg("-s")
+			.arg(old)
+			.arg(new)
+			.stdout(Stdio::piped())
+			.stderr(Stdio::piped())
+			.spawn();
+		let mut cmd = match spawned {
+			Ok(cmd) => cmd,
+			Err(e) => return Err(Error::io("Unable to run", &self.program, e)),
+		};
+		let stdout = match cmd.stdout.take() {
+			Some(stdout) => stdout,
+			None => return Err(Error::other("Unable to read tmutil output")),
+		};
+		cancel.set_child(cmd);
+		let totals = parse_xml_stream(stdout, on_change);
+		// check the exit status first, because a tmutil error will also
+		// cause a parse error
+		let cmd = match cancel.take_child() {
+			Some(cmd) => cmd,
+			None => return Err(Error::other("tmutil process went missing")),
+		};
+		let output = match cmd.wait_with_output() {
+			Ok(output) => output,
+			Err(e) => return Err(Error::io("Unable to wait on", &self.program, e)),
+		};
+		check_cmd_success(&output.status, output.stderr)?;
+		totals
+	}
+}
+
+/// Serves recorded `tmutil` output from a directory:
+///
+/// - `destinationinfo.plist`
+/// - `machinedirectory.txt`
+/// - `isexcluded.txt`, the same for any paths
+/// - `listbackups/<mount point name>.txt`
+/// - `compare/<old backup name>_<new backup name>.plist`
+///
+/// Names are the last component of the mount point or backup path.
+pub struct FixtureBackend {
+	dir: PathBuf,
+}
+
+impl FixtureBackend {
+	pub fn new(dir: impl Into<PathBuf>) -> Self {
+		Self { dir: dir.into() }
+	}
+	fn read(&self, file: &Path) -> Result<Vec<u8>, Error> {
+		let path = self.dir.join(file);
+		match fs::read(&path) {
+			Ok(bytes) => Ok(bytes),
+			Err(e) => Err(Error::io("Unable to read fixture", &path, e)),
+		}
+	}
+}
+
+fn fixture_name(path: &str) -> String {
+	match Path::new(path).file_name() {
+		Some(name) => name.to_string_lossy().to_string(),
+		None => path.replace('/', "_"),
+	}
+}
+
+impl TmutilBackend for FixtureBackend {
+	fn destinationinfo(&self) -> Result<Vec<u8>, Error> {
+		self.read(Path::new("destinationinfo.plist"))
+	}
+
+	fn listbackups(&self, mount_point: &str) -> Result<String, Error> {
+		let file = format!("{}.txt", fixture_name(mount_point));
+		parse_output(self.read(&Path::new("listbackups").join(file))?)
+	}
+
+	fn machinedirectory(&self) -> Result<String, Error> {
+		parse_output(self.read(Path::new("machinedirectory.txt"))?)
+	}
+
+	fn addexclusion(&self, _path: &str) -> Result<(), Error> {
+		Err(Error::other(
+			"Exclusions can't be added when using fixtures",
+		))
+	}
+
+	fn isexcluded(&self, _paths: &[&str]) -> Result<String, Error> {
+		parse_output(self.read(Path::new("isexcluded.txt"))?)
+	}
+
+	fn restore(
+		&self,
+		_source: &str,
+		_destination: &str,
+		_cancel: &CancelToken,
+		_on_path: &mut dyn FnMut(&str),
+	) -> Result<(), Error> {
+		Err(Error::other("Files can't be restored when using fixtures"))
+	}
+
+	fn compare(
+		&self,
+		old: &str,
+		new: &str,
+		_cancel: &CancelToken,
+		on_change: &mut OnChange,
+	) -> Result<Totals, Error> {
+		let file = format!("{}_{}.plist", fixture_name(old), fixture_name(new));
+		let path = self.dir.join("compare").join(file);
+		match fs::File::open(&path) {
+			Ok(file) => parse_xml_stream(file, on_change),
+			Err(e) => Err(Error::io("Unable to read fixture", &path, e)),
+		}
+	}
+}
+
+/// Treats a directory of hard-linked snapshots (for example from rsnapshot or
+/// `rsync --link-dest`) as a single destination, with each subdirectory being
+/// a backup. Comparisons are done natively by `compare_snapshots`.
+pub struct SnapshotBackend {
+	root: PathBuf,
+}
+
+impl SnapshotBackend {
+	pub fn new(root: impl Into<PathBuf>) -> Self {
+		Self { root: root.into() }
+	}
+	fn root_str(&self) -> String {
+		self.root.to_string_lossy().to_string()
+	}
+}
+
+impl TmutilBackend for SnapshotBackend {
+	fn destinationinfo(&self) -> Result<Vec<u8>, Error> {
+		let mut destination = Dictionary::new();
+		destination.insert("ID".to_string(), Value::String(self.root_str()));
+		destination.insert("Kind".to_string(), Value::String("Local".to_string()));
+		destination.insert(
+			"Name".to_string(),
+			Value::String(fixture_name(&self.root_str())),
+		);
+		destination.insert("MountPoint".to_string(), Value::String(self.root_str()));
+		let mut info = Dictionary::new();
+		info.insert(
+			"Destinations".to_string(),
+			Value::Array(vec![Value::Dictionary(destination)]),
+		);
+
+		let mut bytes = Vec::new();
+		match Value::Dictionary(info).to_writer_xml(&mut bytes) {
+			Ok(()) => Ok(bytes),
+			Err(e) => Err(Error::other(format!(
+				"Unable to write destination info: {}",
+				e
+			))),
+		}
+	}
+
+	/// Subdirectories of the root, oldest first by modification time
+	fn listbackups(&self, mount_point: &str) -> Result<String, Error> {
+		let mount_point = Path::new(mount_point);
+		let entries = match fs::read_dir(mount_point) {
+			Ok(entries) => entries,
+			Err(e) => return Err(Error::io("Unable to read snapshots in", mount_point, e)),
+		};
+		let mut snapshots: Vec<(SystemTime, String)> = Vec::new();
+		for entry in entries {
+			let entry = match entry {
+				Ok(entry) => entry,
+				Err(e) => return Err(Error::io("Unable to read snapshots in", mount_point, e)),
+			};
+			let meta = match entry.metadata() {
+				Ok(meta) => meta,
+				Err(e) => return Err(Error::io("Unable to read", &entry.path(), e)),
+			};
+			if !meta.is_dir() {
+				continue;
+			}
+			let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+			snapshots.push((modified, entry.path().to_string_lossy().to_string()));
+		}
+		snapshots.sort();
+		let paths: Vec<_> = snapshots.into_iter().map(|(_, path)| path).collect();
+		Ok(paths.join("\n"))
+	}
+
+	fn machinedirectory(&self) -> Result<String, Error> {
+		Ok(self.root_str())
+	}
+
+	fn addexclusion(&self, _path: &str) -> Result<(), Error> {
+		Err(Error::other("Exclusions can't be added to snapshots"))
+	}
+
+	/// Nothing is excluded from snapshots
+	fn isexcluded(&self, _paths: &[&str]) -> Result<String, Error> {
+		Ok(String::new())
+	}
+
+	/// Copies natively, since snapshots are plain directories
+	fn restore(
+		&self,
+		source: &str,
+		destination: &str,
+		cancel: &CancelToken,
+		on_path: &mut dyn FnMut(&str),
+	) -> Result<(), Error> {
+		eprintln!("Restoring '{}' to '{}'", source, destination);
+		copy_tree(Path::new(source), Path::new(destination), cancel, on_path)
+	}
+
+	fn compare(
+		&self,
+		old: &str,
+		new: &str,
+		cancel: &CancelToken,
+		on_change: &mut OnChange,
+	) -> Result<Totals, Error> {
+		eprintln!("Comparing snapshots '{}' '{}'", old, new);
+		compare_snapshots(Path::new(old), Path::new(new), cancel, on_change)
+	}
+}
+
+#[test]
+fn test_fixture_backend() {
+	use crate::compare;
+	use crate::test_util::{fixture_backend, fixture_backups};
+
+	let backend = fixture_backend();
+	let backups = fixture_backups(&backend);
+	assert_eq!(backups.len(), 3);
+	assert_eq!(backups[0].name, "2023-05-17-123613");
+	assert_eq!(backups[0].timestamp, Some(1684326973));
+	assert_eq!(backups[0].interval, None);
+	assert_eq!(backups[1].interval, Some(1684402202 - 1684326973));
+
+	let dir_map = compare::compare(
+		&backend,
+		&backups[0].path,
+		&backups[1].path,
+		&CancelToken::new(),
+		&mut |_| {},
+	)
+	.unwrap();
+	let root = &dir_map.map[&backups[1].path];
+	assert_eq!(
+		root["Macintosh HD - Data"].size,
+		482113 + 2048 + 612 + 8590983168 + 10311
+	);
+
+	assert!(compare::compare(
+		&backend,
+		&backups[1].path,
+		&backups[0].path,
+		&CancelToken::new(),
+		&mut |_| {}
+	)
+	.is_err());
+}
+
+/// Writes a script that's run instead of tmutil
+#[cfg(test)]
+fn fake_tmutil(name: &str, script: &str) -> Tmutil {
+	use std::os::unix::fs::PermissionsExt;
+
+	let dir = crate::test_util::temp_dir(&format!("fake-tmutil-{}", name));
+	fs::create_dir_all(&dir).unwrap();
+	let path = dir.join(name);
+	fs::write(&path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+	fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+	Tmutil::with_program(path)
+}
+
+#[test]
+fn test_tmutil_errors() {
+	use crate::destinationinfo::destinationinfo;
+	use crate::error::ErrorKind;
+	use crate::listbackups::Destination;
+
+	let compare = |backend: &Tmutil| {
+		let cancel = CancelToken::new();
+		crate::compare::compare(backend, "/old", "/new", &cancel, &mut |_| {})
+	};
+	let mut destination = Destination {
+		backups: None,
+		mount_point: "/Volumes/Backups".to_string(),
+	};
+
+	let missing = Tmutil::with_program("/nonexistent/tmutil");
+	let e = missing.destinationinfo().unwrap_err();
+	assert_eq!(
+		e.kind,
+		ErrorKind::Io {
+			path: "/nonexistent/tmutil".to_string()
+		}
+	);
+	assert!(missing.listbackups("/Volumes/Backups").is_err());
+	assert!(missing.machinedirectory().is_err());
+	assert!(compare(&missing).is_err());
+
+	let failing = fake_tmutil("failing", "echo 'No destinations' >&2; exit 3");
+	let e = destinationinfo(&failing).unwrap_err();
+	assert_eq!(
+		e.kind,
+		ErrorKind::Tmutil {
+			code: Some(3),
+			stderr: "No destinations\n".to_string()
+		}
+	);
+	assert!(destination.load_backups_list(&failing).is_err());
+	assert!(matches!(
+		compare(&failing).unwrap_err().kind,
+		ErrorKind::Tmutil { .. }
+	));
+
+	let garbage = fake_tmutil(
+		"garbage",
+		r#"case "$1" in
+			destinationinfo) echo 'not a plist' ;;
+			listbackups) printf '\377\376' ;;
+			compare) echo '<plist><dict><key>Changes</key><string>' ;;
+		esac"#,
+	);
+	assert!(matches!(
+		destinationinfo(&garbage).unwrap_err().kind,
+		ErrorKind::Parse { .. }
+	));
+	assert!(matches!(
+		garbage.listbackups("/").unwrap_err().kind,
+		ErrorKind::Parse { .. }
+	));
+	assert!(matches!(
+		compare(&garbage).unwrap_err().kind,
+		ErrorKind::Parse { .. }
+	));
+
+	let odd = fake_tmutil(
+		"odd",
+		r#"case "$1" in
+			listbackups) printf '\n\nrelative/../2023-01-02-000000\n./x\n/2023-01-01-000000' ;;
+			machinedirectory) echo '' ;;
+			restore) printf '/to/a\n\n  /to/a/b\n' ;;
+			compare) printf '<plist version="1.0"><dict><key>Changes</key><array>
+				<dict><key>AddedItem</key><dict><key>Path</key><string>relative</string><key>Size</key><integer>1</integer></dict></dict>
+				</array></dict></plist>' ;;
+		esac"#,
+	);
+	let backups = destination.load_backups_list(&odd).unwrap();
+	let names: Vec<_> = backups.iter().map(|b| b.name.as_str()).collect();
+	assert_eq!(names, ["/./x", "2023-01-01-000000", "2023-01-02-000000"]);
+	assert_eq!(backups[2].interval, Some(86400));
+	assert!(compare(&odd).is_err());
+
+	let mut restored = Vec::new();
+	odd.restore("/from/a", "/to/a", &CancelToken::new(), &mut |path| {
+		restored.push(path.to_string())
+	})
+	.unwrap();
+	assert_eq!(restored, ["/to/a", "/to/a/b"]);
+	let restore = failing.restore("/from/a", "/to/a", &CancelToken::new(), &mut |_| {});
+	assert!(matches!(
+		restore.unwrap_err().kind,
+		ErrorKind::Tmutil { .. }
+	));
+}
@@ -0,0 +1,631 @@
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::process::exit;
+use time_machine_inspector::backend::{self, TmutilBackend};
+use time_machine_inspector::compare::{self, CancelToken};
+use time_machine_inspector::destinationinfo::{destinationinfo, DestinationDetail};
+use time_machine_inspector::dir_map::{join_path, DirMap};
+use time_machine_inspector::error::{Error, ErrorKind};
+use time_machine_inspector::exclusions;
+use time_machine_inspector::export::{self, ExportFormat};
+use time_machine_inspector::format_size;
+use time_machine_inspector::history::{self, ChurnAnalysis, PathHistory, PathVersions};
+use time_machine_inspector::listbackups::{Backup, Destination};
+use time_machine_inspector::restore::{self, RestorePlan};
+use time_machine_inspector::top::{self, TopEntry, TopFilter};
+
+const USAGE: &str = "Usage: tmi <command> [--json]
+
+Commands:
+  destinations              List backup destinations
+  backups <destination>     List the backups of a destination, by ID or mount point
+  compare <old> <new>       Compare two backups
+  top <old> <new> [-n <n>] [--prefix <path>] [--ext <ext>]...
+                            List the largest changed files and directories
+                            between two backups, at or below a full path and
+                            with one of the given extensions
+  suggest <old> <new>       List changed paths that are usually safe to exclude
+  exclusions [<path>...]    List the Time Machine exclusions, including sticky
+                            exclusions of the given paths
+  history <destination> <path> [<from> <to>]
+                            Show the changes at or below a path in each backup.
+                            The path is relative to the backups, like
+                            Macintosh HD - Data/Users
+  versions <destination> <path>
+                            List the backups where a path was added, updated
+                            or removed, relative to the backups like history
+  churn <destination> [<from> <to>] [--min-frequency <f>] [-n <n>]
+                            List the paths that changed in at least a fraction
+                            of the backups, 0.5 by default
+  restore <path> <folder> [--dry-run]
+                            Restore a file or folder from a backup into a
+                            folder, asking before replacing anything
+  export <old> <new> [--format csv|json|ncdu|html] [-o <file>]
+                            Export a comparison, to stdout by default. ncdu
+                            exports can be browsed with ncdu -f <file>, html
+                            is a standalone report with a tree and treemap
+
+Set TMI_FIXTURES or TMI_SNAPSHOTS to use recorded tmutil output or a
+directory of hard-linked snapshots instead of tmutil.";
+
+struct Args {
+	command: String,
+	positional: Vec<String>,
+	json: bool,
+	top_n: usize,
+	min_frequency: f64,
+	format: Option<String>,
+	output: Option<String>,
+	top_filter: TopFilter,
+	dry_run: bool,
+}
+
+fn parse_args() -> Result<Args, Error> {
+	let mut args = std::env::args().skip(1);
+	let mut positional = Vec::new();
+	let mut json = false;
+	let mut top_n = 20;
+	let mut min_frequency = 0.5;
+	let mut format = None;
+	let mut output = None;
+	let mut top_filter = TopFilter::default();
+	let mut dry_run = false;
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--json" => json = true,
+			"--dry-run" => dry_run = true,
+			"-n" => {
+				let n = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for -n"))?;
+				top_n = match n.parse() {
+					Ok(n) => n,
+					Err(_) => return Err(Error::other(format!("Invalid value for -n: {}", n))),
+				};
+			}
+			"--min-frequency" => {
+				let value = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for --min-frequency"))?;
+				min_frequency = match value.parse() {
+					Ok(value) => value,
+					Err(_) => {
+						let message = format!("Invalid value for --min-frequency: {}", value);
+						return Err(Error::other(message));
+					}
+				};
+			}
+			"--format" => {
+				let value = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for --format"))?;
+				format = Some(value);
+			}
+			"-o" => {
+				let value = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for -o"))?;
+				output = Some(value);
+			}
+			"--prefix" => {
+				let value = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for --prefix"))?;
+				top_filter.prefix = Some(value);
+			}
+			"--ext" => {
+				let value = args
+					.next()
+					.ok_or_else(|| Error::other("Missing value for --ext"))?;
+				top_filter.extensions.push(value);
+			}
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				exit(0);
+			}
+			_ => positional.push(arg),
+		}
+	}
+	if positional.is_empty() {
+		return Err(Error::other(USAGE));
+	}
+	Ok(Args {
+		command: positional.remove(0),
+		positional,
+		json,
+		top_n,
+		min_frequency,
+		format,
+		output,
+		top_filter,
+		dry_run,
+	})
+}
+
+fn positional<const N: usize>(args: &Args) -> Result<[&str; N], Error> {
+	if args.positional.len() != N {
+		return Err(Error::other(USAGE));
+	}
+	let mut values = [""; N];
+	for (i, value) in args.positional.iter().enumerate() {
+		values[i] = value;
+	}
+	Ok(values)
+}
+
+fn print_json<T: Serialize>(value: &T) -> Result<(), Error> {
+	match serde_json::to_string_pretty(value) {
+		Ok(json) => println!("{}", json),
+		Err(e) => return Err(Error::other(format!("Unable to serialize output: {}", e))),
+	}
+	Ok(())
+}
+
+fn destinations(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	positional::<0>(args)?;
+	let (_, details) = destinationinfo(backend)?;
+	if args.json {
+		return print_json(&details);
+	}
+	let width = details.iter().map(|d| d.id.len()).max().unwrap_or(0);
+	for detail in details {
+		println!(
+			"{:width$}  {}",
+			detail.id,
+			detail.mount_point,
+			width = width
+		);
+	}
+	Ok(())
+}
+
+fn find_destination(
+	backend: &dyn TmutilBackend,
+	query: &str,
+) -> Result<(DestinationDetail, Destination), Error> {
+	let (mut destinations, details) = destinationinfo(backend)?;
+	for detail in details {
+		if detail.id == query || detail.mount_point == query || detail.mount_point_name == query {
+			if let Some(destination) = destinations.remove(&detail.id) {
+				return Ok((detail, destination));
+			}
+		}
+	}
+	Err(Error::new(
+		ErrorKind::DestinationNotFound {
+			id: query.to_string(),
+		},
+		format!("Destination not found: {}", query),
+	))
+}
+
+fn backups(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [query] = positional::<1>(args)?;
+	let (_, mut destination) = find_destination(backend, query)?;
+	let backups = destination.load_backups_list(backend)?;
+	if args.json {
+		return print_json(&backups);
+	}
+	let width = backups.iter().map(|b| b.name.len()).max().unwrap_or(0);
+	for backup in backups {
+		let interval = match backup.interval {
+			Some(interval) => format_interval(interval),
+			None => String::new(),
+		};
+		println!(
+			"{:width$}  {:>7}  {}",
+			backup.name,
+			interval,
+			backup.path,
+			width = width
+		);
+	}
+	Ok(())
+}
+
+/// Formats seconds using their two largest units, like "2d 5h"
+fn format_interval(seconds: u64) -> String {
+	let units = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+	let mut parts = Vec::new();
+	let mut rest = seconds;
+	for (unit, size) in units {
+		if rest >= size || (parts.is_empty() && size == 1) {
+			parts.push(format!("{}{}", rest / size, unit));
+			rest %= size;
+		}
+		if parts.len() == 2 {
+			break;
+		}
+	}
+	parts.join(" ")
+}
+
+fn run_compare(backend: &dyn TmutilBackend, old: &str, new: &str) -> Result<DirMap, Error> {
+	compare::compare(backend, old, new, &CancelToken::new(), &mut |progress| {
+		eprint!(
+			"\r{} changes, {} read",
+			progress.changes,
+			format_size(progress.bytes_read)
+		);
+	})
+	.inspect(|_| eprintln!())
+}
+
+fn print_totals(dir_map: &DirMap) {
+	println!("Added:   {}", format_size(dir_map.totals.added_size));
+	println!("Changed: {}", format_size(dir_map.totals.changed_size));
+	println!("Removed: {}", format_size(dir_map.totals.removed_size));
+}
+
+fn compare(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [old, new] = positional::<2>(args)?;
+	let dir_map = run_compare(backend, old, new)?;
+	if args.json {
+		return print_json(&dir_map);
+	}
+	print_totals(&dir_map);
+
+	// skip past directories with only one entry, like "Macintosh HD - Data"
+	let mut dir = new.trim_end_matches('/').to_string();
+	while let Some(contents) = dir_map.map.get(&dir) {
+		if contents.len() != 1 {
+			break;
+		}
+		let name = contents.keys().next().unwrap();
+		let child = join_path(&dir, name);
+		if !dir_map.map.contains_key(&child) {
+			break;
+		}
+		dir = child;
+	}
+	let mut entries: Vec<_> = match dir_map.map.get(&dir) {
+		Some(contents) => contents.iter().collect(),
+		None => return Ok(()),
+	};
+	entries.sort_by_key(|(_, item)| std::cmp::Reverse(item.size));
+
+	println!();
+	println!("{}", dir);
+	println!("{:>10}  {:>10}  NAME", "SIZE", "REMOVED");
+	for (name, item) in entries {
+		println!(
+			"{:>10}  {:>10}  {}",
+			format_size(item.size),
+			format_size(item.removed_size),
+			name
+		);
+	}
+	Ok(())
+}
+
+fn print_top_entries(title: &str, entries: &[TopEntry]) {
+	println!("{}", title);
+	println!("{:>10}  {:>7}  {:<8}  PATH", "SIZE", "FILES", "CHANGE");
+	for entry in entries {
+		let kind = match entry.kind {
+			Some(kind) => format!("{:?}", kind),
+			None => String::new(),
+		};
+		let size = format_size(entry.size);
+		println!(
+			"{:>10}  {:>7}  {:<8}  {}",
+			size, entry.count, kind, entry.path
+		);
+	}
+}
+
+fn top(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [old, new] = positional::<2>(args)?;
+	let dir_map = run_compare(backend, old, new)?;
+	let top_items = top::top(&dir_map, new, &args.top_filter, args.top_n);
+	if args.json {
+		return print_json(&top_items);
+	}
+	print_top_entries("Largest files", &top_items.files);
+	println!();
+	print_top_entries(
+		"Largest directories, by their own files",
+		&top_items.dirs_by_own_size,
+	);
+	println!();
+	print_top_entries(
+		"Largest directories, with subdirectories",
+		&top_items.dirs_by_subtree_size,
+	);
+	Ok(())
+}
+
+fn suggest(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [old, new] = positional::<2>(args)?;
+	let dir_map = run_compare(backend, old, new)?;
+	let mut suggestions = exclusions::suggest(&dir_map, new, &exclusions::builtin_rules())?;
+	let paths: Vec<_> = suggestions.iter().map(|s| s.source_path.clone()).collect();
+	let preferences = Path::new(exclusions::PREFERENCES_PATH);
+	exclusions::load_exclusions(backend, preferences, &paths)?.mark(&mut suggestions);
+	if args.json {
+		return print_json(&suggestions);
+	}
+	println!("{:>10}  {:<18}  PATH", "SIZE", "RULE");
+	for suggestion in suggestions {
+		let excluded = if suggestion.excluded {
+			" (excluded)"
+		} else {
+			""
+		};
+		println!(
+			"{:>10}  {:<18}  {}{}",
+			format_size(suggestion.size),
+			suggestion.rule,
+			suggestion.source_path,
+			excluded
+		);
+	}
+	Ok(())
+}
+
+fn list_exclusions(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let preferences = Path::new(exclusions::PREFERENCES_PATH);
+	let exclusions = exclusions::load_exclusions(backend, preferences, &args.positional)?;
+	if args.json {
+		return print_json(&exclusions);
+	}
+	for exclusion in exclusions.exclusions {
+		println!(
+			"{:<11}  {}",
+			format!("{:?}", exclusion.source),
+			exclusion.path
+		);
+	}
+	Ok(())
+}
+
+/// The backups of a destination from `from` to `to`, or all of them
+fn load_range(
+	backend: &dyn TmutilBackend,
+	query: &str,
+	range: Option<(&String, &String)>,
+) -> Result<Vec<Backup>, Error> {
+	let (_, mut destination) = find_destination(backend, query)?;
+	let backups = destination.load_backups_list(backend)?;
+	match range {
+		Some((from, to)) => Ok(history::backup_range(backups, from, to)?.to_vec()),
+		None => Ok(backups.clone()),
+	}
+}
+
+/// Without the app's cache, backup directories are read first and comparisons
+/// are only run for backups that can't be read
+fn versions(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [query, path] = positional::<2>(args)?;
+	let backups = load_range(backend, query, None)?;
+	let mut versions = PathVersions::new(path);
+	for (i, new) in backups.iter().enumerate() {
+		let old = i.checked_sub(1).map(|i| &backups[i]);
+		if versions.add_directories(old, new).is_ok() {
+			continue;
+		}
+		match old {
+			Some(old) => {
+				let dir_map = run_compare(backend, &old.path, &new.path)?;
+				versions.add_comparison(new, &dir_map);
+			}
+			None => versions.add_unchecked(new),
+		}
+	}
+	if args.json {
+		return print_json(&versions);
+	}
+	let width = backups.iter().map(|b| b.name.len()).max().unwrap_or(0);
+	println!(
+		"{:width$}  {:<8}  {:>10}  {:>10}",
+		"BACKUP",
+		"CHANGE",
+		"OLD SIZE",
+		"NEW SIZE",
+		width = width
+	);
+	let size = |size: Option<u64>| size.map(format_size).unwrap_or_default();
+	for version in &versions.versions {
+		println!(
+			"{:width$}  {:<8}  {:>10}  {:>10}",
+			version.name,
+			format!("{:?}", version.kind),
+			size(version.old_size),
+			size(version.new_size),
+			width = width
+		);
+	}
+	if !versions.unchecked.is_empty() {
+		println!();
+		println!("Unable to check: {}", versions.unchecked.join(", "));
+	}
+	Ok(())
+}
+
+fn history(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let (query, path, range) = match args.positional.as_slice() {
+		[query, path] => (query, path, None),
+		[query, path, from, to] => (query, path, Some((from, to))),
+		_ => return Err(Error::other(USAGE)),
+	};
+	let backups = load_range(backend, query, range)?;
+	let mut path_history = PathHistory::new(path);
+	for pair in backups.windows(2) {
+		let dir_map = run_compare(backend, &pair[0].path, &pair[1].path)?;
+		path_history.add(&pair[1], &dir_map);
+	}
+	if args.json {
+		return print_json(&path_history);
+	}
+	let width = backups.iter().map(|b| b.name.len()).max().unwrap_or(0);
+	println!(
+		"{:width$}  {:>10}  {:>10}  {:>10}  {:>10}",
+		"BACKUP",
+		"SIZE",
+		"ADDED",
+		"UPDATED",
+		"REMOVED",
+		width = width
+	);
+	for point in &path_history.points {
+		let item = &point.item;
+		println!(
+			"{:width$}  {:>10}  {:>10}  {:>10}  {:>10}",
+			point.name,
+			format_size(item.size),
+			format_size(item.added_size),
+			format_size(item.updated_size),
+			format_size(item.removed_size),
+			width = width
+		);
+	}
+	println!();
+	println!(
+		"Changed in {} of {} backups, {} in total",
+		path_history.changed_count,
+		path_history.points.len(),
+		format_size(path_history.total_size)
+	);
+	Ok(())
+}
+
+fn churn(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let (query, range) = match args.positional.as_slice() {
+		[query] => (query, None),
+		[query, from, to] => (query, Some((from, to))),
+		_ => return Err(Error::other(USAGE)),
+	};
+	let backups = load_range(backend, query, range)?;
+	let mut churn = ChurnAnalysis::new();
+	for pair in backups.windows(2) {
+		let dir_map = run_compare(backend, &pair[0].path, &pair[1].path)?;
+		churn.add(&pair[1], &dir_map);
+	}
+	let report = churn.report(args.min_frequency, args.top_n);
+	if args.json {
+		return print_json(&report);
+	}
+	println!("{:>7}  {:>10}  {:>10}  PATH", "CHANGED", "TOTAL", "MAX");
+	for churner in &report.churners {
+		let changed = format!("{}/{}", churner.count, report.comparisons);
+		let slash = if churner.is_file { "" } else { "/" };
+		println!(
+			"{:>7}  {:>10}  {:>10}  {}{}",
+			changed,
+			format_size(churner.total_size),
+			format_size(churner.max_size),
+			churner.path,
+			slash
+		);
+	}
+	Ok(())
+}
+
+fn export(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [old, new] = positional::<2>(args)?;
+	let format = match args.format.as_deref() {
+		Some(name) => ExportFormat::from_name(name)
+			.ok_or_else(|| Error::other(format!("Unknown export format: {}", name)))?,
+		None if args.json => ExportFormat::Json,
+		None => ExportFormat::Csv,
+	};
+	let dir_map = run_compare(backend, old, new)?;
+	match &args.output {
+		Some(output) => {
+			let path = Path::new(output);
+			let file = match File::create(path) {
+				Ok(file) => file,
+				Err(e) => return Err(Error::io("Unable to create", path, e)),
+			};
+			export::export(&dir_map, new, format, BufWriter::new(file))
+		}
+		None => export::export(&dir_map, new, format, io::stdout().lock()),
+	}
+}
+
+fn confirm(question: &str) -> bool {
+	eprint!("{} [y/N] ", question);
+	let mut answer = String::new();
+	if io::stdin().read_line(&mut answer).is_err() {
+		return false;
+	}
+	matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn restore(backend: &dyn TmutilBackend, args: &Args) -> Result<(), Error> {
+	let [source, folder] = positional::<2>(args)?;
+	let plan = RestorePlan::new(source, folder)?;
+	if args.dry_run {
+		if args.json {
+			return print_json(&plan);
+		}
+		for entry in &plan.entries {
+			let size = if entry.is_dir {
+				String::new()
+			} else {
+				format_size(entry.size)
+			};
+			println!("{:>10}  {}", size, entry.path);
+		}
+		if plan.entries.len() < plan.file_count as usize {
+			println!("...");
+		}
+		println!();
+		println!(
+			"{} items, {} to {}",
+			plan.file_count,
+			format_size(plan.total_size),
+			plan.destination
+		);
+		if plan.exists {
+			println!("{} already exists", plan.destination);
+		}
+		return Ok(());
+	}
+	let overwrite = plan.exists && confirm(&format!("Replace {}?", plan.destination));
+	if plan.exists && !overwrite {
+		return Err(Error::cancelled());
+	}
+	restore::restore(
+		backend,
+		&plan,
+		overwrite,
+		&CancelToken::new(),
+		&mut |progress| {
+			eprint!("\r{} of {} restored", progress.done, progress.total);
+		},
+	)?;
+	eprintln!();
+	println!("Restored {}", plan.destination);
+	Ok(())
+}
+
+fn main() {
+	let result = parse_args().and_then(|args| {
+		let backend = backend::from_env();
+		let backend = backend.0.as_ref();
+		match args.command.as_str() {
+			"destinations" => destinations(backend, &args),
+			"backups" => backups(backend, &args),
+			"compare" => compare(backend, &args),
+			"top" => top(backend, &args),
+			"suggest" => suggest(backend, &args),
+			"exclusions" => list_exclusions(backend, &args),
+			"history" => history(backend, &args),
+			"churn" => churn(backend, &args),
+			"versions" => versions(backend, &args),
+			"export" => export(backend, &args),
+			"restore" => restore(backend, &args),
+			command => Err(Error::other(format!(
+				"Unknown command: {}\n\n{}",
+				command, USAGE
+			))),
+		}
+	});
+	if let Err(e) = result {
+		eprintln!("{}", e);
+		exit(1);
+	}
+}
@@ -0,0 +1,237 @@
+use crate::compare::Totals;
+use crate::dir_map::DirMap;
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Bump when the format of `DirMap` changes, so that old entries are discarded
+const CACHE_VERSION: u32 = 1;
+pub const DEFAULT_MAX_SIZE: u64 = 1_000_000_000;
+
+/// Completed comparisons stored on disk. A backup never changes once it's
+/// finished, so a comparison of two backups can be reused across restarts.
+///
+/// Entries are JSON files at `<dir>/v<version>/<destination>/<old>_<new>.json`.
+/// When the cache grows past `max_size`, the least recently used entries are
+/// removed.
+pub struct DiskCache {
+	dir: PathBuf,
+	max_size: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry<M> {
+	destination_id: String,
+	old: String,
+	new: String,
+	map: M,
+}
+
+/// A `DirMap` with the map skipped
+#[derive(Deserialize)]
+struct TotalsOnly {
+	totals: Totals,
+}
+
+fn safe_name(name: &str) -> String {
+	let name = name.trim_end_matches('/');
+	let name = name.rsplit('/').next().unwrap_or(name);
+	name.chars()
+		.map(|c| match c {
+			'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+			_ => '_',
+		})
+		.collect()
+}
+
+impl DiskCache {
+	pub fn new(dir: impl Into<PathBuf>, max_size: u64) -> Self {
+		Self {
+			dir: dir.into(),
+			max_size,
+		}
+	}
+	fn version_dir(&self) -> PathBuf {
+		self.dir.join(format!("v{}", CACHE_VERSION))
+	}
+	fn entry_path(&self, destination_id: &str, old: &str, new: &str) -> PathBuf {
+		let file = format!("{}_{}.json", safe_name(old), safe_name(new));
+		self.version_dir()
+			.join(safe_name(destination_id))
+			.join(file)
+	}
+
+	/// Gets a comparison, or `None` if it's not cached or the entry is
+	/// unreadable
+	pub fn load(&self, destination_id: &str, old: &str, new: &str) -> Option<DirMap> {
+		let path = self.entry_path(destination_id, old, new);
+		let file = File::open(&path).ok()?;
+		let entry: CacheEntry<DirMap> = match serde_json::from_reader(BufReader::new(&file)) {
+			Ok(entry) => entry,
+			Err(e) => {
+				eprintln!("Removing invalid cache entry {}: {}", path.display(), e);
+				let _ = fs::remove_file(&path);
+				return None;
+			}
+		};
+		// names are shortened in the file name, so check the full paths
+		if entry.destination_id != destination_id || entry.old != old || entry.new != new {
+			return None;
+		}
+		// the modification time is used to find the least recently used entries
+		if let Ok(file) = File::options().write(true).open(&path) {
+			let _ = file.set_modified(SystemTime::now());
+		}
+		Some(entry.map)
+	}
+
+	/// Gets only the totals of a comparison, which is faster than loading
+	/// the whole entry
+	pub fn load_totals(&self, destination_id: &str, old: &str, new: &str) -> Option<Totals> {
+		let path = self.entry_path(destination_id, old, new);
+		let file = File::open(path).ok()?;
+		let entry: CacheEntry<TotalsOnly> = serde_json::from_reader(BufReader::new(file)).ok()?;
+		if entry.destination_id != destination_id || entry.old != old || entry.new != new {
+			return None;
+		}
+		Some(entry.map.totals)
+	}
+
+	pub fn store(
+		&self,
+		destination_id: &str,
+		old: &str,
+		new: &str,
+		dir_map: &DirMap,
+	) -> Result<(), Error> {
+		let path = self.entry_path(destination_id, old, new);
+		let parent = path.parent().unwrap_or(&self.dir);
+		if let Err(e) = fs::create_dir_all(parent) {
+			return Err(Error::io("Unable to create cache directory", parent, e));
+		}
+		let entry = CacheEntry {
+			destination_id: destination_id.to_string(),
+			old: old.to_string(),
+			new: new.to_string(),
+			map: dir_map,
+		};
+		// write to a temporary file first, so a partial entry is never read
+		let tmp_path = path.with_extension("json.tmp");
+		let file = match File::create(&tmp_path) {
+			Ok(file) => file,
+			Err(e) => return Err(Error::io("Unable to create cache entry", &tmp_path, e)),
+		};
+		if let Err(e) = serde_json::to_writer(BufWriter::new(file), &entry) {
+			let _ = fs::remove_file(&tmp_path);
+			return Err(Error::other(format!("Unable to write cache entry: {}", e)));
+		}
+		if let Err(e) = fs::rename(&tmp_path, &path) {
+			return Err(Error::io("Unable to write cache entry", &path, e));
+		}
+		self.evict()
+	}
+
+	/// Removes entries from older cache versions, and the least recently
+	/// used entries until the cache fits in `max_size`
+	fn evict(&self) -> Result<(), Error> {
+		let version_dir = self.version_dir();
+		for entry in read_dir(&self.dir)? {
+			if entry != version_dir {
+				remove(&entry)?;
+			}
+		}
+
+		let mut entries = Vec::new();
+		let mut total_size = 0;
+		for destination_dir in read_dir(&version_dir)? {
+			for path in read_dir(&destination_dir)? {
+				let meta = match fs::metadata(&path) {
+					Ok(meta) => meta,
+					Err(_) => continue,
+				};
+				let modified = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+				total_size += meta.len();
+				entries.push((modified, meta.len(), path));
+			}
+		}
+		entries.sort();
+		for (_, size, path) in entries {
+			if total_size <= self.max_size {
+				break;
+			}
+			remove(&path)?;
+			total_size -= size;
+		}
+		Ok(())
+	}
+
+	/// Removes every cached comparison
+	pub fn clear(&self) -> Result<(), Error> {
+		match fs::remove_dir_all(&self.dir) {
+			Ok(()) => Ok(()),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+			Err(e) => Err(Error::io("Unable to clear cache", &self.dir, e)),
+		}
+	}
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(e) => return Err(Error::io("Unable to read cache directory", dir, e)),
+	};
+	Ok(entries.filter_map(|e| e.ok()).map(|e| e.path()).collect())
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+	let result = if path.is_dir() {
+		fs::remove_dir_all(path)
+	} else {
+		fs::remove_file(path)
+	};
+	match result {
+		Ok(()) => Ok(()),
+		Err(e) => Err(Error::io("Unable to remove cache entry", path, e)),
+	}
+}
+
+#[test]
+fn test_disk_cache() {
+	use crate::test_util::{add, temp_dir};
+	use std::time::Duration;
+
+	let dir = temp_dir("cache");
+	let mut dir_map = DirMap::new();
+	dir_map.add_change(add("/b/2/file", 5)).unwrap();
+	dir_map.totals.added_size = 5;
+
+	let cache = DiskCache::new(&dir, DEFAULT_MAX_SIZE);
+	assert!(cache.load("dest", "/b/1", "/b/2").is_none());
+	cache.store("dest", "/b/1", "/b/2", &dir_map).unwrap();
+	let loaded = cache.load("dest", "/b/1", "/b/2").unwrap();
+	assert_eq!(loaded.totals.added_size, 5);
+	assert_eq!(loaded.map["/b/2"]["file"].added_count, 1);
+	let totals = cache.load_totals("dest", "/b/1", "/b/2").unwrap();
+	assert_eq!(totals.added_size, 5);
+	// same file name, different backups
+	assert!(cache.load("dest", "/other/1", "/b/2").is_none());
+	assert!(cache.load("other", "/b/1", "/b/2").is_none());
+
+	// only room for one entry, so the least recently used one is evicted
+	let entry_size = fs::metadata(cache.entry_path("dest", "/b/1", "/b/2"))
+		.unwrap()
+		.len();
+	let cache = DiskCache::new(&dir, entry_size * 3 / 2);
+	std::thread::sleep(Duration::from_millis(10));
+	cache.store("dest", "/b/2", "/b/3", &dir_map).unwrap();
+	assert!(cache.load("dest", "/b/1", "/b/2").is_none());
+	assert!(cache.load("dest", "/b/2", "/b/3").is_some());
+
+	cache.clear().unwrap();
+	assert!(cache.load("dest", "/b/2", "/b/3").is_none());
+	assert!(!dir.exists());
+}
@@ -1,38 +1,21 @@
-use crate::dir_map::DirMap;
-use crate::listbackups::{Backup, Destinations};
-use crate::{compare, throw};
+use crate::sizes::SizeEstimates;
 use serde::Serialize;
 use specta::Type;
 use std::collections::HashMap;
 use std::fs::File;
-use std::process::ExitStatus;
 use std::sync::{Mutex, MutexGuard};
 use tauri::api::dialog;
-use tauri::{command, State, Window};
+use tauri::{command, AppHandle, Manager, State, Window};
+use time_machine_inspector::backend::BackendState;
+use time_machine_inspector::cache::DiskCache;
+use time_machine_inspector::compare::{CancelToken, CompareProgress};
+use time_machine_inspector::destinationinfo::DestinationDetail;
+use time_machine_inspector::dir_map::DirMap;
+use time_machine_inspector::error::{Error, ErrorKind};
+use time_machine_inspector::listbackups::{Backup, Destinations};
+use time_machine_inspector::{compare, destinationinfo};
 
-pub fn parse_output(bytes: Vec<u8>) -> Result<String, String> {
-	match String::from_utf8(bytes) {
-		Ok(s) => Ok(s),
-		Err(e) => throw!("Unable to parse output: {}", e),
-	}
-}
-
-fn code_to_str(code: Option<i32>) -> String {
-	match code {
-		Some(code) => format!("{}", code),
-		None => "None".to_string(),
-	}
-}
-
-pub fn check_cmd_success(status: &ExitStatus, stderr: Vec<u8>) -> Result<(), String> {
-	if !status.success() {
-		let stderr = parse_output(stderr)?;
-		throw!("tmutil error {}:\n{}", code_to_str(status.code()), stderr);
-	}
-	Ok(())
-}
-
-pub async fn full_disk_access(dialog_window: Window) -> Result<(), String> {
+pub async fn full_disk_access(dialog_window: Window) -> Result<(), Error> {
 	match File::open("/Library/Preferences/com.apple.TimeMachine.plist") {
 		Ok(_file) => {}
 		Err(e) => match e.kind() {
@@ -48,12 +31,16 @@ pub async fn full_disk_access(dialog_window: Window) -> Result<(), String> {
 					3. Add and enable Time Machine Inspector on the right",
 				);
 
-				open::that(
-					"x-apple.systempreferences:com.apple.preference.security?Privacy_AllFiles",
-				)
-				.unwrap();
+				let settings_url =
+					"x-apple.systempreferences:com.apple.preference.security?Privacy_AllFiles";
+				if let Err(e) = open::that(settings_url) {
+					eprintln!("Unable to open System Preferences: {}", e);
+				}
 
-				return Ok(());
+				return Err(Error::new(
+					ErrorKind::FullDiskAccess,
+					"Full Disk Access is required",
+				));
 			}
 			_ => eprintln!("Unable to open Time Machine preferences: {}", e),
 		},
@@ -64,45 +51,73 @@ pub async fn full_disk_access(dialog_window: Window) -> Result<(), String> {
 pub struct DestinationsState(pub Mutex<Destinations>);
 
 impl DestinationsState {
-	pub fn lock(&self) -> Result<MutexGuard<Destinations>, String> {
+	pub fn lock(&self) -> Result<MutexGuard<Destinations>, Error> {
 		match self.0.lock() {
 			Ok(mutex) => Ok(mutex),
-			Err(e) => throw!("Unable to acquire mutex: {}", e),
+			Err(e) => Err(Error::other(format!("Unable to acquire mutex: {}", e))),
 		}
 	}
 }
 
+#[command]
+#[specta::specta]
+pub async fn destinationinfo(
+	state: State<'_, DestinationsState>,
+	backend: State<'_, BackendState>,
+) -> Result<Vec<DestinationDetail>, Error> {
+	let (destinations, details) = destinationinfo::destinationinfo(backend.0.as_ref())?;
+	state.lock()?.destinations = Some(destinations);
+	Ok(details)
+}
+
+/// With `estimate_sizes`, the size of each backup is computed in the
+/// background and emitted as `BACKUP_SIZE_EVENT`
 #[command]
 #[specta::specta]
 pub async fn load_backup_list(
 	destination_id: String,
 	refresh: bool,
+	estimate_sizes: bool,
 	w: Window,
 	state: State<'_, DestinationsState>,
-) -> Result<Vec<Backup>, String> {
+	backend: State<'_, BackendState>,
+	size_estimates: State<'_, SizeEstimates>,
+) -> Result<Vec<Backup>, Error> {
 	// get cached backup_list
+	let mut cached = None;
 	if !refresh {
 		let mut destinations = state.lock()?;
 		let destination = destinations.get_destination(&destination_id)?;
-		match &destination.backups {
-			Some(backups) => return Ok(backups.clone()),
-			None => {}
-		}
+		cached = destination.backups.clone();
 	}
 
-	full_disk_access(w).await?;
-	let mut destinations = state.lock()?;
-	let destination = destinations.get_destination(&destination_id)?;
-	let backups = destination.load_backups_list()?;
-	println!("Listed backups {:#?}", backups);
+	let backups = match cached {
+		Some(backups) => backups,
+		None => {
+			full_disk_access(w.clone()).await?;
+			let mut destinations = state.lock()?;
+			let destination = destinations.get_destination(&destination_id)?;
+			destination.load_backups_list(backend.0.as_ref())?.clone()
+		}
+	};
 
-	Ok(backups.clone())
+	if estimate_sizes {
+		let app = w.app_handle();
+		size_estimates.start(&destination_id, backups.clone(), refresh, app)?;
+	}
+	Ok(backups)
 }
 
-#[derive(Serialize, Clone, Type, Debug)]
-pub struct LoadedBackupItem {
-	#[specta(type = u32)] // tauri bigint fix
-	pub size: u64,
+/// `None` if there's no cache directory
+pub struct CacheState(pub Option<DiskCache>);
+
+#[command]
+#[specta::specta]
+pub async fn clear_cache(cache: State<'_, CacheState>) -> Result<(), Error> {
+	match &cache.0 {
+		Some(cache) => cache.clear(),
+		None => Ok(()),
+	}
 }
 
 #[derive(Serialize, Clone)]
@@ -111,18 +126,34 @@ pub struct LoadedBackup {
 	pub new: String,
 	pub map: DirMap,
 	pub loading: bool,
+	pub progress: Option<CompareProgress>,
+	#[serde(skip)]
+	pub cancel: CancelToken,
 }
 pub type LoadedBackupsMap = HashMap<(String, String), LoadedBackup>;
 
 pub struct LoadedBackups(pub Mutex<LoadedBackupsMap>);
 
 impl LoadedBackups {
-	pub fn lock(&self) -> Result<MutexGuard<LoadedBackupsMap>, String> {
+	pub fn lock(&self) -> Result<MutexGuard<LoadedBackupsMap>, Error> {
 		match self.0.lock() {
 			Ok(mutex) => Ok(mutex),
-			Err(e) => throw!("Unable to lock backup list: {}", e),
+			Err(e) => Err(Error::other(format!("Unable to lock backup list: {}", e))),
 		}
 	}
+	/// Cancels the comparisons matching `filter` that are still loading
+	pub fn cancel_where(&self, filter: impl Fn(&LoadedBackup) -> bool) -> Result<(), Error> {
+		let mut loaded_backups = self.lock()?;
+		loaded_backups.retain(|_, loaded_backup| {
+			if loaded_backup.loading && filter(loaded_backup) {
+				println!("Cancelling {} -> {}", loaded_backup.old, loaded_backup.new);
+				loaded_backup.cancel.cancel();
+				return false;
+			}
+			true
+		});
+		Ok(())
+	}
 }
 
 #[derive(Serialize, Clone, Type)]
@@ -130,23 +161,49 @@ pub struct BackupInfo {
 	pub old: String,
 	pub new: String,
 	pub loading: bool,
+	/// Latest progress, if loading
+	pub progress: Option<CompareProgress>,
 }
 
 #[command]
 #[specta::specta]
-pub async fn backups_info(state: State<'_, LoadedBackups>) -> Result<Vec<BackupInfo>, String> {
+pub async fn backups_info(state: State<'_, LoadedBackups>) -> Result<Vec<BackupInfo>, Error> {
 	let map = state.lock()?;
 	let info = map.values().map(|b| BackupInfo {
 		old: b.old.clone(),
 		new: b.new.clone(),
 		loading: b.loading,
+		progress: b.progress.clone(),
 	});
 	Ok(info.collect())
 }
 
-async fn do_compare(old: &str, new: &str, w: Window) -> Result<DirMap, String> {
-	full_disk_access(w).await?;
-	Ok(compare::compare(&old, &new)?)
+async fn do_compare(
+	backend: &BackendState,
+	state: &LoadedBackups,
+	old: &str,
+	new: &str,
+	cancel: &CancelToken,
+	w: Window,
+) -> Result<DirMap, Error> {
+	full_disk_access(w.clone()).await?;
+	let old_new = (old.to_string(), new.to_string());
+	Ok(compare::compare(
+		backend.0.as_ref(),
+		&old,
+		&new,
+		cancel,
+		&mut |progress| {
+			if let Ok(mut loaded_backups) = state.lock() {
+				if let Some(loaded_backup) = loaded_backups.get_mut(&old_new) {
+					loaded_backup.progress = Some(progress.clone());
+				}
+			}
+			if let Err(e) = w.emit(compare::PROGRESS_EVENT, progress) {
+				eprintln!("Unable to emit progress: {}", e);
+			}
+		},
+	)?)
 }
 
 #[command]
@@ -158,45 +215,157 @@ pub async fn get_backup<'a>(
 	w: Window,
 	state: State<'_, LoadedBackups>,
 	destinations_state: State<'_, DestinationsState>,
-) -> Result<DirMap, String> {
-	let (old_b, new_b) = {
+	backend: State<'_, BackendState>,
+	cache: State<'_, CacheState>,
+) -> Result<DirMap, Error> {
+	let old_b = {
 		let mut destinations = destinations_state.lock()?;
 		let destination = destinations.get_destination(&destination_id)?;
-		let backups = match &destination.backups {
-			Some(data) => data,
-			None => throw!("Backup list not loaded"),
-		};
-		let new_pos = match backups.iter().position(|p| p.path == new_b) {
-			Some(pos) => pos,
-			None => throw!("Unable to find backup {}", new_b),
-		};
-		let old_b = backups
-			.get(new_pos - 1)
-			.ok_or("No previous backup")?
-			.clone()
-			.path;
-		(old_b, new_b)
+		let new_pos = destination.backup_position(&new_b)?;
+		let backups = destination.backups()?;
+		match new_pos.checked_sub(1) {
+			Some(old_pos) => backups[old_pos].path.clone(),
+			None => {
+				return Err(Error::new(
+					ErrorKind::NoPreviousBackup,
+					"No previous backup",
+				))
+			}
+		}
 	};
-	let old_new = (old_b.clone(), new_b.clone());
+	load_comparison(
+		&destination_id,
+		old_b,
+		new_b,
+		refresh,
+		w,
+		&state,
+		&backend,
+		&cache,
+	)
+	.await
+}
+
+/// Checks that `old_b` and `new_b` are backups of the destination, with
+/// `old_b` older than `new_b`
+fn check_backup_order(
+	destinations_state: &DestinationsState,
+	destination_id: &str,
+	old_b: &str,
+	new_b: &str,
+) -> Result<(), Error> {
+	let mut destinations = destinations_state.lock()?;
+	let destination = destinations.get_destination(destination_id)?;
+	let old_pos = destination.backup_position(old_b)?;
+	let new_pos = destination.backup_position(new_b)?;
+	if old_pos >= new_pos {
+		let message = format!("{} is not older than {}", old_b, new_b);
+		let kind = ErrorKind::BackupOrder {
+			old: old_b.to_string(),
+			new: new_b.to_string(),
+		};
+		return Err(Error::new(kind, message));
+	}
+	Ok(())
+}
+
+/// Compares any two backups of a destination. `old_b` has to be older than
+/// `new_b`.
+#[command]
+#[specta::specta]
+pub async fn compare_backups(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	refresh: bool,
+	w: Window,
+	state: State<'_, LoadedBackups>,
+	destinations_state: State<'_, DestinationsState>,
+	backend: State<'_, BackendState>,
+	cache: State<'_, CacheState>,
+) -> Result<DirMap, Error> {
+	check_backup_order(&destinations_state, &destination_id, &old_b, &new_b)?;
+	load_comparison(
+		&destination_id,
+		old_b,
+		new_b,
+		refresh,
+		w,
+		&state,
+		&backend,
+		&cache,
+	)
+	.await
+}
+
+/// Gets the comparison from `LoadedBackups` or the disk cache, or runs it
+async fn load_comparison(
+	destination_id: &str,
+	old_b: String,
+	new_b: String,
+	refresh: bool,
+	w: Window,
+	state: &LoadedBackups,
+	backend: &BackendState,
+	cache: &CacheState,
+) -> Result<DirMap, Error> {
+	let old_new = (old_b, new_b);
+	ensure_loaded(destination_id, &old_new, refresh, w, state, backend, cache).await?;
+	with_loaded(state, &old_new, |dir_map| dir_map.clone())
+}
+
+/// Makes sure `LoadedBackups` has the comparison of `old_new`, from the disk
+/// cache or by running it. Fails if it's already being run.
+async fn ensure_loaded(
+	destination_id: &str,
+	old_new: &(String, String),
+	refresh: bool,
+	w: Window,
+	state: &LoadedBackups,
+	backend: &BackendState,
+	cache: &CacheState,
+) -> Result<(), Error> {
+	let (old_b, new_b) = old_new;
 
 	// get cached dir_map
 	if !refresh {
-		let loaded_backups = state.lock()?;
-		match loaded_backups.get(&old_new) {
-			Some(loaded_backup) => {
-				return Ok(loaded_backup.map.clone());
+		if let Some(loaded_backup) = state.lock()?.get(old_new) {
+			if !loaded_backup.loading {
+				return Ok(());
 			}
-			None => {}
+		}
+		let cached = match &cache.0 {
+			Some(cache) => cache.load(destination_id, old_b, new_b),
+			None => None,
+		};
+		if let Some(dir_map) = cached {
+			println!("Loaded {} -> {} from cache", old_b, new_b);
+			let backup = LoadedBackup {
+				old: old_b.clone(),
+				new: new_b.clone(),
+				map: dir_map,
+				loading: false,
+				progress: None,
+				cancel: CancelToken::new(),
+			};
+			state.lock()?.insert(old_new.clone(), backup);
+			return Ok(());
 		}
 	}
 
+	let cancel = CancelToken::new();
 	{
 		let mut loaded_backups = state.lock()?;
-		match loaded_backups.get_mut(&old_new) {
+		match loaded_backups.get_mut(old_new) {
 			Some(loaded_backup) => {
 				if (loaded_backup).loading {
-					throw!("Already loading backup");
+					return Err(Error::new(
+						ErrorKind::AlreadyLoading,
+						"Already loading backup",
+					));
 				}
+				loaded_backup.loading = true;
+				loaded_backup.cancel = cancel.clone();
 			}
 			None => {
 				let backup = LoadedBackup {
@@ -204,28 +373,183 @@ pub async fn get_backup<'a>(
 					new: new_b.clone(),
 					map: DirMap::new(),
 					loading: true,
+					progress: None,
+					cancel: cancel.clone(),
 				};
 				loaded_backups.insert(old_new.clone(), backup);
 			}
 		}
 	}
 
-	match do_compare(&old_b, &new_b, w).await {
-		Ok(dir_map) => {
-			let mut loaded_backups = state.lock()?;
-			let backup = LoadedBackup {
-				old: old_b,
-				new: new_b,
-				map: dir_map.clone(),
-				loading: false,
-			};
-			loaded_backups.insert(old_new, backup);
-			return Ok(dir_map);
+	let result = do_compare(backend, state, old_b, new_b, &cancel, w).await;
+	let dir_map = {
+		let mut loaded_backups = state.lock()?;
+		// a cancelled comparison's entry has already been removed, and may
+		// have been replaced by a new comparison of the same backups
+		if cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		match result {
+			Ok(dir_map) => dir_map,
+			Err(e) => {
+				loaded_backups.remove(old_new);
+				return Err(e);
+			}
+		}
+	};
+	// storing can take a while, so it's done without holding the lock
+	if let Some(cache) = &cache.0 {
+		if let Err(e) = cache.store(destination_id, old_b, new_b, &dir_map) {
+			eprintln!("Unable to cache comparison: {}", e);
+		}
+	}
+	let mut loaded_backups = state.lock()?;
+	if cancel.is_cancelled() {
+		return Err(Error::cancelled());
+	}
+	let backup = LoadedBackup {
+		old: old_b.clone(),
+		new: new_b.clone(),
+		map: dir_map,
+		loading: false,
+		progress: None,
+		cancel,
+	};
+	loaded_backups.insert(old_new.clone(), backup);
+	Ok(())
+}
+
+/// Runs `f` with the loaded comparison of `old_new`, while holding the lock
+fn with_loaded<T>(
+	state: &LoadedBackups,
+	old_new: &(String, String),
+	f: impl FnOnce(&DirMap) -> T,
+) -> Result<T, Error> {
+	match state.lock()?.get(old_new) {
+		Some(loaded_backup) if loaded_backup.loading => Err(Error::new(
+			ErrorKind::AlreadyLoading,
+			"The comparison is still loading",
+		)),
+		Some(loaded_backup) => Ok(f(&loaded_backup.map)),
+		// removed when it's cancelled
+		None => Err(Error::cancelled()),
+	}
+}
+
+/// Runs `f` with the comparison of two of the destination's backups, loaded
+/// like in `compare_backups` so that it's kept in `LoadedBackups`, where it
+/// can be cancelled and reused. `f` runs while `LoadedBackups` is locked.
+pub async fn with_loaded_comparison<T>(
+	w: &Window,
+	destination_id: &str,
+	old_b: &str,
+	new_b: &str,
+	f: impl FnOnce(&DirMap) -> T,
+) -> Result<T, Error> {
+	let app = w.app_handle();
+	let destinations_state = app.state::<DestinationsState>();
+	check_backup_order(&destinations_state, destination_id, old_b, new_b)?;
+	let state = app.state::<LoadedBackups>();
+	let old_new = (old_b.to_string(), new_b.to_string());
+	ensure_loaded(
+		destination_id,
+		&old_new,
+		false,
+		w.clone(),
+		&state,
+		&app.state::<BackendState>(),
+		&app.state::<CacheState>(),
+	)
+	.await?;
+	with_loaded(&state, &old_new, f)
+}
+
+/// Runs `f` with the comparison from `LoadedBackups` or the disk cache, or
+/// runs the comparison and stores it in the disk cache. Unlike
+/// `load_comparison`, new comparisons aren't kept in `LoadedBackups`.
+pub fn with_comparison<T>(
+	app: &AppHandle,
+	destination_id: &str,
+	old: &str,
+	new: &str,
+	cancel: &CancelToken,
+	f: impl FnOnce(&DirMap) -> T,
+) -> Result<T, Error> {
+	let old_new = (old.to_string(), new.to_string());
+	if let Some(loaded_backup) = app.state::<LoadedBackups>().lock()?.get(&old_new) {
+		if !loaded_backup.loading {
+			return Ok(f(&loaded_backup.map));
 		}
-		Err(e) => {
-			let mut loaded_backups = state.lock()?;
-			loaded_backups.remove(&old_new);
-			return Err(e);
+	}
+	let cache = app.state::<CacheState>();
+	let cache = cache.0.as_ref();
+	if let Some(dir_map) = cache.and_then(|c| c.load(destination_id, old, new)) {
+		return Ok(f(&dir_map));
+	}
+
+	let backend = app.state::<BackendState>();
+	let dir_map = compare::compare(backend.0.as_ref(), old, new, cancel, &mut |_| {})?;
+	if let Some(cache) = cache {
+		if let Err(e) = cache.store(destination_id, old, new, &dir_map) {
+			eprintln!("Unable to cache comparison: {}", e);
 		}
 	}
+	Ok(f(&dir_map))
+}
+
+/// Runs `f` with the comparison from `LoadedBackups` or the disk cache.
+/// Returns `None` without running `f` if neither has it.
+pub fn with_cached_comparison<T>(
+	app: &AppHandle,
+	destination_id: &str,
+	old: &str,
+	new: &str,
+	f: impl FnOnce(&DirMap) -> T,
+) -> Result<Option<T>, Error> {
+	let old_new = (old.to_string(), new.to_string());
+	if let Some(loaded_backup) = app.state::<LoadedBackups>().lock()?.get(&old_new) {
+		if !loaded_backup.loading {
+			return Ok(Some(f(&loaded_backup.map)));
+		}
+	}
+	let cache = app.state::<CacheState>();
+	match cache
+		.0
+		.as_ref()
+		.and_then(|c| c.load(destination_id, old, new))
+	{
+		Some(dir_map) => Ok(Some(f(&dir_map))),
+		None => Ok(None),
+	}
+}
+
+#[command]
+#[specta::specta]
+pub async fn cancel_backup(
+	old_b: String,
+	new_b: String,
+	state: State<'_, LoadedBackups>,
+) -> Result<(), Error> {
+	state.cancel_where(|b| b.old == old_b && b.new == new_b)
+}
+
+/// Cancels the comparisons of the destination's backups
+#[command]
+#[specta::specta]
+pub async fn close_destination(
+	destination_id: String,
+	state: State<'_, LoadedBackups>,
+	destinations_state: State<'_, DestinationsState>,
+	size_estimates: State<'_, SizeEstimates>,
+) -> Result<(), Error> {
+	size_estimates.cancel_where(|id| id == destination_id)?;
+	let paths: Vec<String> = {
+		let mut destinations = destinations_state.lock()?;
+		let destination = destinations.get_destination(&destination_id)?;
+		match &destination.backups {
+			Some(backups) => backups.iter().map(|b| b.path.clone()).collect(),
+			None => return Ok(()),
+		}
+	};
+	state.cancel_where(|b| paths.contains(&b.new))
 }
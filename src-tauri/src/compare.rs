@@ -1,12 +1,72 @@
-use crate::cmd::check_cmd_success;
+use crate::backend::TmutilBackend;
 use crate::dir_map::DirMap;
-use crate::{reset_dur, throw};
-use plist::Value;
+use crate::error::Error;
+use crate::reset_dur;
+use plist::stream::{Event, OwnedEvent, XmlReader};
+use plist::Deserializer;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader, BufWriter, Cursor};
-use std::process::{Command, Stdio};
-use std::time::Instant;
+use specta::Type;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::Child;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub const PROGRESS_EVENT: &str = "compare-progress";
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Called with each change and the number of bytes of output read so far
+pub type OnChange<'a> = dyn FnMut(Change, u64) -> Result<(), Error> + 'a;
+
+/// Lets a running comparison be cancelled from another thread. Backends that
+/// spawn a process hand it to the token so that cancelling can kill it.
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<Mutex<CancelState>>);
+
+#[derive(Default)]
+struct CancelState {
+	cancelled: bool,
+	child: Option<Child>,
+}
+
+impl CancelToken {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	fn state(&self) -> MutexGuard<'_, CancelState> {
+		match self.0.lock() {
+			Ok(state) => state,
+			Err(poisoned) => poisoned.into_inner(),
+		}
+	}
+	pub fn cancel(&self) {
+		let mut state = self.state();
+		state.cancelled = true;
+		if let Some(child) = &mut state.child {
+			if let Err(e) = child.kill() {
+				eprintln!("Unable to kill process: {}", e);
+			}
+		}
+	}
+	pub fn is_cancelled(&self) -> bool {
+		self.state().cancelled
+	}
+	/// Holds on to the child process until `take_child`, killing it if the
+	/// token is cancelled in the meantime
+	pub fn set_child(&self, mut child: Child) {
+		let mut state = self.state();
+		if state.cancelled {
+			let _ = child.kill();
+		}
+		state.child = Some(child);
+	}
+	pub fn take_child(&self) -> Option<Child> {
+		self.state().child.take()
+	}
+}
 
 #[derive(Serialize, Debug)]
 pub struct Comparison {
@@ -14,15 +74,6 @@ pub struct Comparison {
 	pub totals: Totals,
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(deny_unknown_fields)]
-struct ComparisonXml {
-	#[serde(rename = "Changes")]
-	changes: Vec<Value>,
-	#[serde(rename = "Totals")]
-	totals: Totals,
-}
-
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(untagged)]
 pub enum Change {
@@ -31,6 +82,82 @@ pub enum Change {
 	Delete(Delete),
 }
 
+impl Change {
+	/// Moves a removed item from the old backup to the same location in the
+	/// new backup, so that it shows up next to the other changes
+	pub fn rebase_removed(&mut self, old: &str, new: &str) {
+		if let Change::Delete(delete) = self {
+			if let Some(rest) = delete.removed_item.path.strip_prefix(old) {
+				if rest.is_empty() || rest.starts_with('/') {
+					delete.removed_item.path = new.to_string() + rest;
+				}
+			}
+		}
+	}
+	/// Path of the newer item, or the removed item for deletions
+	pub fn path(&self) -> &str {
+		match self {
+			Change::Add(add) => &add.added_item.path,
+			Change::Update(update) => &update.newer_item.path,
+			Change::Delete(delete) => &delete.removed_item.path,
+		}
+	}
+}
+
+/// Kind of difference between two versions of an updated item
+#[derive(Serialize, Deserialize, Clone, Copy, Type, Debug, PartialEq)]
+pub enum Difference {
+	Content,
+	Size,
+	Permissions,
+	Ownership,
+	Dates,
+	ExtendedAttributes,
+	Flags,
+	Metadata,
+	Other,
+}
+
+impl Difference {
+	/// Classifies a difference reported by tmutil
+	pub fn from_tmutil(difference: &str) -> Self {
+		let difference = difference.to_lowercase();
+		let matches = |words: &[&str]| words.iter().any(|word| difference.contains(word));
+		// Checked before content, since "metadata" contains "data"
+		if matches(&["xattr", "extended"]) {
+			Difference::ExtendedAttributes
+		} else if matches(&["metadata"]) {
+			Difference::Metadata
+		} else if matches(&["content", "data"]) {
+			Difference::Content
+		} else if matches(&["size"]) {
+			Difference::Size
+		} else if matches(&["perm", "mode", "acl"]) {
+			Difference::Permissions
+		} else if matches(&["owner", "uid", "gid", "group"]) {
+			Difference::Ownership
+		} else if matches(&["date", "time"]) {
+			Difference::Dates
+		} else if matches(&["flag"]) {
+			Difference::Flags
+		} else if matches(&["attr"]) {
+			Difference::Metadata
+		} else {
+			Difference::Other
+		}
+	}
+	pub fn from_tmutil_list(differences: &[String]) -> Vec<Self> {
+		let mut list: Vec<Self> = Vec::new();
+		for difference in differences {
+			let difference = Difference::from_tmutil(difference);
+			if !list.contains(&difference) {
+				list.push(difference);
+			}
+		}
+		list
+	}
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct Add {
@@ -65,104 +192,329 @@ pub struct Item {
 	pub size: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Clone, Type, Debug, Default)]
 #[serde(deny_unknown_fields)]
 pub struct Totals {
 	#[serde(alias = "AddedSize")]
+	#[specta(type = u32)] // tauri bigint fix
 	pub added_size: u64,
 	#[serde(alias = "ChangedSize")]
+	#[specta(type = u32)] // tauri bigint fix
 	pub changed_size: u64,
 	#[serde(alias = "RemovedSize")]
+	#[specta(type = u32)] // tauri bigint fix
 	pub removed_size: u64,
 }
 
-pub fn parse_xml(lines: &[u8]) -> Result<Comparison, String> {
-	let comparison: ComparisonXml = match plist::from_bytes(lines) {
-		Ok(v) => v,
-		Err(e) => throw!("Unable to parse response: {}", e),
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct CompareProgress {
+	pub old: String,
+	pub new: String,
+	#[specta(type = u32)] // tauri bigint fix
+	pub bytes_read: u64,
+	#[specta(type = u32)] // tauri bigint fix
+	pub changes: u64,
+	pub current_path: String,
+	pub elapsed_ms: u32,
+}
+
+pub fn parse_xml(lines: &[u8]) -> Result<Comparison, Error> {
+	let mut changes = Vec::new();
+	let totals = parse_xml_stream(lines, &mut |change, _| {
+		changes.push(change);
+		Ok(())
+	})?;
+	Ok(Comparison { changes, totals })
+}
+
+/// Incrementally parses the output of `tmutil compare -X`, calling `on_change`
+/// for every change as soon as it has been read. Only one change is held in
+/// memory at a time.
+pub fn parse_xml_stream<R: Read>(reader: R, on_change: &mut OnChange) -> Result<Totals, Error> {
+	let bytes_read = Rc::new(Cell::new(0));
+	let reader = CountingReader {
+		inner: reader,
+		count: bytes_read.clone(),
 	};
+	let mut events = XmlReader::new(BufReader::new(reader));
+	let mut totals = None;
 
-	let mut changes = Vec::with_capacity(comparison.changes.len());
-
-	for change_dict in &comparison.changes {
-		let change: Change = match deserialize_value(&change_dict) {
-			Ok(v) => v,
-			Err(e) => {
-				throw!(
-					"Unable to read change: {}\nReceived value: {:#?}",
-					e,
-					change_dict
-				);
+	match next_event(&mut events)? {
+		Event::StartDictionary(_) => {}
+		event => {
+			return Err(Error::parse_value(
+				format!(
+					"Unable to parse response: Expected dictionary, found {:?}",
+					event
+				),
+				event,
+			))
+		}
+	}
+	loop {
+		let key = match next_event(&mut events)? {
+			Event::String(key) => key,
+			Event::EndCollection => break,
+			event => {
+				return Err(Error::parse_value(
+					format!("Unable to parse response: Expected key, found {:?}", event),
+					event,
+				))
 			}
 		};
-		changes.push(change);
+		match key.as_ref() {
+			"Changes" => {
+				match next_event(&mut events)? {
+					Event::StartArray(_) => {}
+					event => {
+						return Err(Error::parse_value(
+							format!(
+								"Unable to parse response: Expected array, found {:?}",
+								event
+							),
+							event,
+						))
+					}
+				}
+				loop {
+					let change_events = match next_event(&mut events)? {
+						Event::EndCollection => break,
+						event => read_value(&mut events, event)?,
+					};
+					let change: Change = match deserialize_events(&change_events) {
+						Ok(v) => v,
+						Err(e) => {
+							return Err(Error::parse_value(
+								format!("Unable to read change: {}", e),
+								change_events,
+							));
+						}
+					};
+					on_change(change, bytes_read.get())?;
+				}
+			}
+			"Totals" => {
+				let first = next_event(&mut events)?;
+				let totals_events = read_value(&mut events, first)?;
+				totals = Some(deserialize_events(&totals_events)?);
+			}
+			key => {
+				return Err(Error::parse_value(
+					format!("Unable to parse response: Unknown field {}", key),
+					key,
+				))
+			}
+		}
 	}
 
-	Ok(Comparison {
-		changes,
-		totals: comparison.totals,
-	})
+	match totals {
+		Some(totals) => Ok(totals),
+		None => Err(Error::parse(
+			"Unable to parse response: Missing field Totals",
+		)),
+	}
 }
 
-fn deserialize_value<T: DeserializeOwned>(value: &Value) -> Result<T, String> {
-	let mut buf_writer = BufWriter::new(Vec::new());
-	match value.to_writer_binary(&mut buf_writer) {
-		Ok(_) => {}
-		Err(_) => throw!("Failed serializing change"),
-	};
-	let bytes = match buf_writer.into_inner() {
-		Ok(v) => v,
-		Err(_) => throw!("Error change_buf_writer.into_inner"),
-	};
-	let cursor = Cursor::new(bytes);
-	let change: T = match plist::from_reader(cursor) {
-		Ok(v) => v,
-		Err(e) => throw!("Unable to read item: {}", e.to_string()),
-	};
-	Ok(change)
+struct CountingReader<R> {
+	inner: R,
+	count: Rc<Cell<u64>>,
 }
 
-pub fn compare(old: &str, new: &str) -> Result<DirMap, String> {
-	let mut anchor = Instant::now();
-
-	println!("tmutil compare -X -s '{}' '{}'", old, new);
-
-	let mut cmd = Command::new("tmutil")
-		.arg("compare")
-		.arg("-X")
-		.arg("-s")
-		.arg(old)
-		.arg(new)
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.spawn()
-		.expect("Error calling command");
-
-	reset_dur(&mut anchor);
+impl<R: Read> Read for CountingReader<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		self.count.set(self.count.get() + n as u64);
+		Ok(n)
+	}
+}
 
-	let mut child_out = BufReader::new(cmd.stdout.as_mut().unwrap());
-	let mut lines = Vec::new();
+fn next_event<R: BufRead>(events: &mut XmlReader<R>) -> Result<OwnedEvent, Error> {
+	match events.next() {
+		Some(Ok(event)) => Ok(event),
+		Some(Err(e)) => Err(Error::parse(format!("Unable to parse response: {}", e))),
+		None => Err(Error::parse(
+			"Unable to parse response: Unexpected end of output",
+		)),
+	}
+}
 
+/// Collects the events of the value starting with `first`
+fn read_value<R: BufRead>(
+	events: &mut XmlReader<R>,
+	first: OwnedEvent,
+) -> Result<Vec<OwnedEvent>, Error> {
+	let mut depth = 0;
+	let mut value_events = Vec::new();
+	let mut event = first;
 	loop {
-		match child_out.read_until(b'\n', &mut lines) {
-			Ok(0) => break,
+		match event {
+			Event::StartArray(_) | Event::StartDictionary(_) => depth += 1,
+			Event::EndCollection => depth -= 1,
 			_ => {}
-		};
+		}
+		value_events.push(event);
+		if depth == 0 {
+			return Ok(value_events);
+		}
+		event = next_event(events)?;
 	}
+}
 
-	let output = cmd.wait_with_output().expect("Failed ot wait on command");
-	check_cmd_success(&output.status, output.stderr)?;
+/// Borrows the strings and data of an event instead of copying them
+fn borrow_event(event: &OwnedEvent) -> Event<'_> {
+	match event {
+		Event::String(s) => Event::String(Cow::Borrowed(s)),
+		Event::Data(data) => Event::Data(Cow::Borrowed(data)),
+		event => event.clone(),
+	}
+}
 
-	println!("\u{23f1}  {:.3}ms reading output", reset_dur(&mut anchor));
+/// Deserializes borrowed events, so that they're still around for the error
+/// message if it fails
+fn deserialize_events<T: DeserializeOwned>(events: &[OwnedEvent]) -> Result<T, Error> {
+	let mut deserializer = Deserializer::new(events.iter().map(|e| Ok(borrow_event(e))));
+	match T::deserialize(&mut deserializer) {
+		Ok(v) => Ok(v),
+		Err(e) => Err(Error::parse(format!("Unable to read item: {}", e))),
+	}
+}
 
-	let comparison = parse_xml(&lines)?;
-	println!("{:#?}", comparison.totals);
+pub fn compare(
+	backend: &dyn TmutilBackend,
+	old: &str,
+	new: &str,
+	cancel: &CancelToken,
+	on_progress: &mut dyn FnMut(CompareProgress),
+) -> Result<DirMap, Error> {
+	let start = Instant::now();
+	let mut anchor = Instant::now();
+	let mut last_progress = Instant::now();
+	let mut progress = CompareProgress {
+		old: old.to_string(),
+		new: new.to_string(),
+		bytes_read: 0,
+		changes: 0,
+		current_path: String::new(),
+		elapsed_ms: 0,
+	};
 
-	println!("\u{23f1}  {:.3}ms parse xml", reset_dur(&mut anchor));
+	let mut dir_map = DirMap::new();
+	let result = backend.compare(old, new, cancel, &mut |mut change, bytes_read| {
+		if cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		progress.bytes_read = bytes_read;
+		progress.changes += 1;
+		if last_progress.elapsed() >= PROGRESS_INTERVAL {
+			last_progress = Instant::now();
+			progress.current_path = change.path().to_string();
+			progress.elapsed_ms = start.elapsed().as_millis() as u32;
+			on_progress(progress.clone());
+		}
+		change.rebase_removed(old, new);
+		dir_map.add_change(change)
+	});
+	if cancel.is_cancelled() {
+		eprintln!("Cancelled comparison");
+		return Err(Error::cancelled());
+	}
+	dir_map.totals = result?;
 
-	let dir_map = DirMap::from_comparison(comparison)?;
+	eprintln!(
+		"\u{23f1}  {:.3}ms comparing and constructing map",
+		reset_dur(&mut anchor)
+	);
 
-	println!("\u{23f1}  {:.3}ms constructing map", reset_dur(&mut anchor));
+	progress.current_path = String::new();
+	progress.elapsed_ms = start.elapsed().as_millis() as u32;
+	on_progress(progress);
 
 	Ok(dir_map)
 }
+
+#[test]
+fn test_parse_xml_stream() {
+	let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>Changes</key>
+	<array>
+		<dict>
+			<key>Differences</key>
+			<array><string>Content</string></array>
+			<key>NewerItem</key>
+			<dict><key>Path</key><string>/b/file</string><key>Size</key><integer>20</integer></dict>
+			<key>OlderItem</key>
+			<dict><key>Path</key><string>/a/file</string><key>Size</key><integer>10</integer></dict>
+		</dict>
+		<dict>
+			<key>RemovedItem</key>
+			<dict><key>Path</key><string>/a/gone</string><key>Size</key><integer>5</integer></dict>
+		</dict>
+	</array>
+	<key>Totals</key>
+	<dict>
+		<key>AddedSize</key><integer>0</integer>
+		<key>ChangedSize</key><integer>20</integer>
+		<key>RemovedSize</key><integer>5</integer>
+	</dict>
+</dict>
+</plist>"#;
+	let comparison = parse_xml(xml.as_bytes()).unwrap();
+	let dir_map = DirMap::from_comparison(parse_xml(xml.as_bytes()).unwrap()).unwrap();
+	let file = dir_map.map["/b"]["file"].change.as_ref().unwrap();
+	assert_eq!((file.old_size, file.new_size), (Some(10), Some(20)));
+	assert_eq!(file.differences, vec![Difference::Content]);
+	assert_eq!(comparison.totals.removed_size, 5);
+	assert!(matches!(comparison.changes[0], Change::Update(_)));
+	assert!(matches!(comparison.changes[1], Change::Delete(_)));
+
+	let truncated = &xml[..xml.find("<key>Totals").unwrap()];
+	assert!(parse_xml(truncated.as_bytes()).is_err());
+	let unknown = xml.replace("RemovedItem", "MovedItem");
+	assert!(parse_xml(unknown.as_bytes()).is_err());
+}
+
+#[test]
+fn test_cancel_token() {
+	let child = std::process::Command::new("sleep")
+		.arg("10")
+		.spawn()
+		.unwrap();
+	let cancel = CancelToken::new();
+	cancel.set_child(child);
+	cancel.clone().cancel();
+	assert!(cancel.is_cancelled());
+	let status = cancel.take_child().unwrap().wait().unwrap();
+	assert!(!status.success());
+
+	let backend = crate::test_util::fixture_backend();
+	let old = "/x/2023-05-17-123613.backup";
+	let new = "/x/2023-05-18-093002.backup";
+	let result = compare(&backend, old, new, &cancel, &mut |_| {});
+	assert_eq!(result.unwrap_err().kind, crate::error::ErrorKind::Cancelled);
+}
+
+#[test]
+fn test_difference_from_tmutil() {
+	let differences: Vec<String> = ["Metadata", "Extended Attributes", "Content", "Data"]
+		.iter()
+		.map(|d| d.to_string())
+		.collect();
+	assert_eq!(
+		Difference::from_tmutil_list(&differences),
+		[
+			Difference::Metadata,
+			Difference::ExtendedAttributes,
+			Difference::Content
+		]
+	);
+	assert_eq!(
+		Difference::from_tmutil("Modification Date"),
+		Difference::Dates
+	);
+	assert_eq!(
+		Difference::from_tmutil("Permissions"),
+		Difference::Permissions
+	);
+}
@@ -1,11 +1,9 @@
-use crate::cmd::{check_cmd_success, DestinationsState};
+use crate::backend::TmutilBackend;
+use crate::error::Error;
 use crate::listbackups::Destination;
-use crate::throw;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use std::collections::HashMap;
-use std::process::Command;
-use tauri::{command, State};
 
 #[derive(Deserialize, Debug)]
 #[serde(deny_unknown_fields)]
@@ -38,22 +36,15 @@ pub struct DestinationDetail {
 	pub mount_point_name: String,
 }
 
-#[command]
-#[specta::specta]
-pub async fn destinationinfo(
-	state: State<'_, DestinationsState>,
-) -> Result<Vec<DestinationDetail>, String> {
-	let output = Command::new("tmutil")
-		.arg("destinationinfo")
-		.arg("-X")
-		.output()
-		.expect("Error calling command");
-	check_cmd_success(&output.status, output.stderr.clone())?;
-	println!("Success running destinationinfo");
+/// Destinations with a mount point, by ID, along with their details
+pub fn destinationinfo(
+	backend: &dyn TmutilBackend,
+) -> Result<(HashMap<String, Destination>, Vec<DestinationDetail>), Error> {
+	let output = backend.destinationinfo()?;
 
-	let output_xml: DestinationInfoXml = match plist::from_bytes(&output.stdout) {
+	let output_xml: DestinationInfoXml = match plist::from_bytes(&output) {
 		Ok(v) => v,
-		Err(e) => throw!("Unable to parse response: {}", e),
+		Err(e) => return Err(Error::parse(format!("Unable to parse response: {}", e))),
 	};
 
 	let mut destinations_map = HashMap::new();
@@ -68,7 +59,6 @@ pub async fn destinationinfo(
 			);
 		}
 	}
-	state.lock()?.destinations = Some(destinations_map.clone());
 
 	let destinations_details = output_xml
 		.destinations
@@ -91,5 +81,5 @@ pub async fn destinationinfo(
 		})
 		.collect();
 
-	Ok(destinations_details)
+	Ok((destinations_map, destinations_details))
 }
@@ -1,29 +1,115 @@
-use crate::cmd::LoadedBackupItem;
-use crate::{compare, throw};
-use serde::Serialize;
+use crate::compare;
+use crate::compare::{Difference, Totals};
+use crate::error::Error;
+use serde::{Deserialize, Serialize};
 use specta::Type;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 
+#[derive(Serialize, Deserialize, Clone, Copy, Type, Debug, PartialEq)]
+pub enum ChangeKind {
+	Added,
+	Updated,
+	Removed,
+}
+
+/// Totals of the changes at or below a path
+#[derive(Serialize, Deserialize, Clone, Type, Debug, Default)]
+pub struct LoadedBackupItem {
+	/// Bytes added or updated, which is what the backup stored
+	#[specta(type = u32)] // tauri bigint fix
+	pub size: u64,
+	#[specta(type = u32)] // tauri bigint fix
+	pub added_size: u64,
+	#[specta(type = u32)] // tauri bigint fix
+	pub updated_size: u64,
+	#[specta(type = u32)] // tauri bigint fix
+	pub removed_size: u64,
+	pub added_count: u32,
+	pub updated_count: u32,
+	pub removed_count: u32,
+	/// The change of this exact path, if it's a changed file
+	pub change: Option<ItemChange>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Type, Debug)]
+pub struct ItemChange {
+	pub kind: ChangeKind,
+	/// Size in the old backup, for updated and removed items
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub old_size: Option<u64>,
+	/// Size in the new backup, for added and updated items
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub new_size: Option<u64>,
+	pub differences: Vec<Difference>,
+	/// Differences as reported by tmutil
+	pub raw_differences: Vec<String>,
+}
+
+impl LoadedBackupItem {
+	pub fn add(&mut self, kind: ChangeKind, size: u64) {
+		match kind {
+			ChangeKind::Added => {
+				self.size += size;
+				self.added_size += size;
+				self.added_count += 1;
+			}
+			ChangeKind::Updated => {
+				self.size += size;
+				self.updated_size += size;
+				self.updated_count += 1;
+			}
+			ChangeKind::Removed => {
+				self.removed_size += size;
+				self.removed_count += 1;
+			}
+		}
+	}
+}
+
 pub type DirContents = HashMap<String, LoadedBackupItem>;
 
-#[derive(Serialize, Clone, Debug, Type, Default)]
+#[derive(Serialize, Deserialize, Clone, Debug, Type, Default)]
 pub struct DirMap {
 	pub map: HashMap<String, DirContents>,
+	pub totals: Totals,
 }
 
-fn get_parent<'a>(path: &'a Path) -> Result<&'a Path, String> {
+fn get_parent<'a>(path: &'a Path) -> Result<&'a Path, Error> {
 	match path.parent() {
 		Some(p) => Ok(p),
-		None => throw!("No parent of path {}", path.to_string_lossy()),
+		None => Err(Error::other(format!(
+			"No parent of path {}",
+			path.to_string_lossy()
+		))),
 	}
 }
-fn get_basename<'a>(path: &'a Path) -> Result<&'a OsStr, String> {
+fn get_basename<'a>(path: &'a Path) -> Result<&'a OsStr, Error> {
 	match path.file_name() {
 		Some(p) => Ok(p),
-		None => throw!("No base of path {}", path.to_string_lossy()),
+		None => Err(Error::other(format!(
+			"No base of path {}",
+			path.to_string_lossy()
+		))),
+	}
+}
+
+pub fn join_path(dir: &str, name: &str) -> String {
+	if dir == "/" {
+		format!("/{}", name)
+	} else {
+		format!("{}/{}", dir, name)
+	}
+}
+
+/// Whether `path` is `dir` or inside it
+pub fn is_at_or_below(path: &str, dir: &str) -> bool {
+	let dir = dir.trim_end_matches('/');
+	match path.strip_prefix(dir) {
+		Some(rest) => rest.is_empty() || rest.starts_with('/') || dir.is_empty(),
+		None => false,
 	}
 }
 
@@ -31,39 +117,90 @@ impl DirMap {
 	pub fn new() -> Self {
 		Self {
 			map: HashMap::new(),
+			totals: Totals::default(),
 		}
 	}
 	pub fn get_or_create_dir(&mut self, path: String) -> &mut DirContents {
 		self.map.entry(path).or_insert(HashMap::new())
 	}
-	pub fn item_entry(&mut self, path: &Path) -> Result<Entry<String, LoadedBackupItem>, String> {
+	pub fn item_entry(&mut self, path: &Path) -> Result<Entry<String, LoadedBackupItem>, Error> {
 		let dir = get_parent(path)?.to_string_lossy().to_string();
 		let basename = get_basename(path)?.to_string_lossy().to_string();
 
 		let dir_contents = self.get_or_create_dir(dir);
 		Ok(dir_contents.entry(basename))
 	}
-	pub fn from_comparison(comparison: compare::Comparison) -> Result<Self, String> {
+	pub fn from_comparison(comparison: compare::Comparison) -> Result<Self, Error> {
 		let mut dir_map = DirMap::new();
-
 		for change in comparison.changes {
-			let new_item = match change {
-				compare::Change::Add(add) => add.added_item,
-				compare::Change::Update(update) => update.newer_item,
-				compare::Change::Delete(_) => continue,
-			};
-			let path = PathBuf::from(new_item.path);
+			dir_map.add_change(change)?;
+		}
+		dir_map.totals = comparison.totals;
+		Ok(dir_map)
+	}
+	/// Gets the item at a full path, if anything changed at or below it
+	pub fn get_item(&self, path: &str) -> Option<&LoadedBackupItem> {
+		let path = Path::new(path);
+		let dir = path.parent()?.to_string_lossy();
+		let basename = path.file_name()?.to_string_lossy();
+		self.map.get(dir.as_ref())?.get(basename.as_ref())
+	}
+	/// Changed files and other leaf items, with their full path
+	pub fn changed_items(&self) -> impl Iterator<Item = (String, &LoadedBackupItem)> {
+		self.map.iter().flat_map(|(dir, contents)| {
+			contents
+				.iter()
+				.filter(|(_, item)| item.change.is_some())
+				.map(move |(name, item)| (join_path(dir, name), item))
+		})
+	}
+	pub fn add_change(&mut self, change: compare::Change) -> Result<(), Error> {
+		let (item, item_change) = match change {
+			compare::Change::Add(add) => {
+				let item_change = ItemChange {
+					kind: ChangeKind::Added,
+					old_size: None,
+					new_size: Some(add.added_item.size),
+					differences: Vec::new(),
+					raw_differences: Vec::new(),
+				};
+				(add.added_item, item_change)
+			}
+			compare::Change::Update(update) => {
+				let item_change = ItemChange {
+					kind: ChangeKind::Updated,
+					old_size: Some(update.older_item.size),
+					new_size: Some(update.newer_item.size),
+					differences: Difference::from_tmutil_list(&update.differences),
+					raw_differences: update.differences,
+				};
+				(update.newer_item, item_change)
+			}
+			compare::Change::Delete(delete) => {
+				let item_change = ItemChange {
+					kind: ChangeKind::Removed,
+					old_size: Some(delete.removed_item.size),
+					new_size: None,
+					differences: Vec::new(),
+					raw_differences: Vec::new(),
+				};
+				(delete.removed_item, item_change)
+			}
+		};
+		let kind = item_change.kind;
+		let path = PathBuf::from(item.path);
+		self.item_entry(&path)?
+			.or_insert(LoadedBackupItem::default())
+			.change = Some(item_change);
 
-			for ancestor in path.ancestors() {
-				if ancestor == Path::new("/") {
-					break;
-				}
-				let item = dir_map
-					.item_entry(ancestor)?
-					.or_insert(LoadedBackupItem { size: 0 });
-				item.size += new_item.size;
+		for ancestor in path.ancestors() {
+			if ancestor == Path::new("/") {
+				break;
 			}
+			self.item_entry(ancestor)?
+				.or_insert(LoadedBackupItem::default())
+				.add(kind, item.size);
 		}
-		Ok(dir_map)
+		Ok(())
 	}
 }
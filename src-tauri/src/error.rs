@@ -0,0 +1,124 @@
+use serde::Serialize;
+use specta::Type;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+/// What went wrong. Serialized with a `kind` field, so the UI can handle
+/// each case differently.
+#[derive(Serialize, Clone, Type, Debug, PartialEq)]
+#[serde(tag = "kind")]
+pub enum ErrorKind {
+	/// tmutil exited unsuccessfully
+	Tmutil {
+		code: Option<i32>,
+		stderr: String,
+	},
+	/// The app hasn't been granted Full Disk Access
+	FullDiskAccess,
+	PermissionDenied {
+		path: String,
+	},
+	Io {
+		path: String,
+	},
+	/// Output that couldn't be parsed, with the offending value if there is one
+	Parse {
+		value: Option<String>,
+	},
+	DestinationsNotLoaded,
+	DestinationNotFound {
+		id: String,
+	},
+	BackupListNotLoaded,
+	BackupNotFound {
+		path: String,
+	},
+	NoPreviousBackup,
+	/// The old backup of a comparison isn't older than the new one
+	BackupOrder {
+		old: String,
+		new: String,
+	},
+	AlreadyLoading,
+	/// Something is in the way, and replacing it needs confirmation
+	AlreadyExists {
+		path: String,
+	},
+	Cancelled,
+	Other,
+}
+
+#[derive(Serialize, Clone, Type, Debug, PartialEq)]
+pub struct Error {
+	#[serde(flatten)]
+	pub kind: ErrorKind,
+	/// Message to show the user
+	pub message: String,
+}
+
+impl Error {
+	pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+		Self {
+			kind,
+			message: message.into(),
+		}
+	}
+	pub fn other(message: impl Into<String>) -> Self {
+		Self::new(ErrorKind::Other, message)
+	}
+	pub fn cancelled() -> Self {
+		Self::new(ErrorKind::Cancelled, "Cancelled")
+	}
+	pub fn tmutil(code: Option<i32>, stderr: String) -> Self {
+		let code_str = match code {
+			Some(code) => format!("{}", code),
+			None => "None".to_string(),
+		};
+		let message = format!("tmutil error {}:\n{}", code_str, stderr);
+		Self::new(ErrorKind::Tmutil { code, stderr }, message)
+	}
+	/// For example `Error::io("Unable to read", path, e)` becomes
+	/// "Unable to read /path: error"
+	pub fn io(action: &str, path: &Path, e: io::Error) -> Self {
+		let message = format!("{} {}: {}", action, path.to_string_lossy(), e);
+		let path = path.to_string_lossy().to_string();
+		match e.kind() {
+			io::ErrorKind::PermissionDenied => {
+				Self::new(ErrorKind::PermissionDenied { path }, message)
+			}
+			_ => Self::new(ErrorKind::Io { path }, message),
+		}
+	}
+	pub fn parse(message: impl Into<String>) -> Self {
+		Self::new(ErrorKind::Parse { value: None }, message)
+	}
+	pub fn parse_value(message: impl Into<String>, value: impl fmt::Debug) -> Self {
+		let value = Some(format!("{:#?}", value));
+		Self::new(ErrorKind::Parse { value }, message)
+	}
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}", self.message)
+	}
+}
+
+impl std::error::Error for Error {}
+
+#[test]
+fn test_error_serialization() {
+	let error = Error::tmutil(Some(1), "No such backup".to_string());
+	let json = serde_json::to_value(&error).unwrap();
+	assert_eq!(json["kind"], "Tmutil");
+	assert_eq!(json["code"], 1);
+	assert_eq!(json["stderr"], "No such backup");
+	assert_eq!(json["message"], "tmutil error 1:\nNo such backup");
+
+	let json = serde_json::to_value(Error::cancelled()).unwrap();
+	assert_eq!(
+		json,
+		serde_json::json!({ "kind": "Cancelled", "message": "Cancelled" })
+	);
+}
@@ -0,0 +1,117 @@
+use crate::analysis::{self, RunningAnalysis};
+use crate::cmd::{self, DestinationsState};
+use serde::Serialize;
+use specta::Type;
+use std::path::Path;
+use tauri::{command, State, Window};
+use time_machine_inspector::backend::BackendState;
+use time_machine_inspector::error::Error;
+use time_machine_inspector::exclusions::{self, Exclusion, ExclusionRule, Suggestion};
+use time_machine_inspector::history::{self, PathHistory};
+
+/// Changed paths in the comparison of `old_b` and `new_b` that match the
+/// built-in rules or `user_rules`, marked if they're already excluded. The
+/// comparison is loaded like in `get_backup`.
+#[command]
+#[specta::specta]
+pub async fn exclusion_suggestions(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	user_rules: Vec<ExclusionRule>,
+	w: Window,
+	backend: State<'_, BackendState>,
+) -> Result<Vec<Suggestion>, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	let mut rules = exclusions::builtin_rules();
+	rules.extend(user_rules);
+	let mut suggestions =
+		cmd::with_loaded_comparison(&w, &destination_id, &old_b, &new_b, |dir_map| {
+			exclusions::suggest(dir_map, &new_b, &rules)
+		})
+		.await??;
+
+	let paths: Vec<_> = suggestions.iter().map(|s| s.source_path.clone()).collect();
+	let preferences = Path::new(exclusions::PREFERENCES_PATH);
+	exclusions::load_exclusions(backend.0.as_ref(), preferences, &paths)?.mark(&mut suggestions);
+	Ok(suggestions)
+}
+
+#[derive(Serialize, Clone, Type)]
+pub struct ComparisonExclusions {
+	pub exclusions: Vec<Exclusion>,
+	/// Full paths of the changed items in the comparison that are excluded
+	/// now, so they won't be in the next backup
+	pub excluded_paths: Vec<String>,
+}
+
+/// The Time Machine exclusions, and which changed items in the comparison of
+/// `old_b` and `new_b` they exclude. Sticky exclusions are only found for
+/// the changed directories.
+#[command]
+#[specta::specta]
+pub async fn comparison_exclusions(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	w: Window,
+	backend: State<'_, BackendState>,
+) -> Result<ComparisonExclusions, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	cmd::with_loaded_comparison(&w, &destination_id, &old_b, &new_b, |dir_map| {
+		let dirs = exclusions::changed_dirs(dir_map, &new_b);
+		let preferences = Path::new(exclusions::PREFERENCES_PATH);
+		let exclusions = exclusions::load_exclusions(backend.0.as_ref(), preferences, &dirs)?;
+		Ok(ComparisonExclusions {
+			excluded_paths: exclusions.excluded_paths(dir_map, &new_b),
+			exclusions: exclusions.exclusions,
+		})
+	})
+	.await?
+}
+
+#[derive(Serialize, Clone, Type)]
+pub struct ExclusionResult {
+	/// The path that's excluded on disk
+	pub source_path: String,
+	/// Bytes that excluding the path would have saved in each backup
+	pub saved: PathHistory,
+	/// `false` for dry runs
+	pub excluded: bool,
+}
+
+/// Excludes `path` from future backups with `tmutil addexclusion`. `path` is
+/// relative to the backups, like in suggestions. The bytes it would have
+/// saved are computed for the backups from `from_b` to `to_b`, and with
+/// `dry_run` nothing is excluded.
+#[command]
+#[specta::specta]
+pub async fn add_exclusion(
+	destination_id: String,
+	path: String,
+	from_b: String,
+	to_b: String,
+	dry_run: bool,
+	w: Window,
+	destinations_state: State<'_, DestinationsState>,
+	backend: State<'_, BackendState>,
+	running: State<'_, RunningAnalysis>,
+) -> Result<ExclusionResult, Error> {
+	let backups = analysis::get_backups(&destinations_state, &destination_id)?;
+	let range = history::backup_range(&backups, &from_b, &to_b)?;
+	let mut saved = PathHistory::new(&path);
+	analysis::for_each_comparison(&destination_id, range, w, &running, |new, dir_map| {
+		saved.add(new, dir_map)
+	})
+	.await?;
+
+	let source_path = exclusions::source_path(&path);
+	if !dry_run {
+		backend.0.addexclusion(&source_path)?;
+	}
+	Ok(ExclusionResult {
+		source_path,
+		saved,
+		excluded: !dry_run,
+	})
+}
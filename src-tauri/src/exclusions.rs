@@ -0,0 +1,456 @@
+use crate::backend::TmutilBackend;
+use crate::dir_map::{join_path, DirMap, LoadedBackupItem};
+use crate::error::Error;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Paths to suggest excluding. `pattern` is a glob matched against paths
+/// relative to the backups, like "Macintosh HD - Data/Users/alice". `*`
+/// matches within a path component and `**/` matches any number of
+/// components.
+#[derive(Serialize, Deserialize, Clone, Type, Debug)]
+pub struct ExclusionRule {
+	pub name: String,
+	pub pattern: String,
+}
+
+impl ExclusionRule {
+	fn new(name: &str, pattern: &str) -> Self {
+		Self {
+			name: name.to_string(),
+			pattern: pattern.to_string(),
+		}
+	}
+}
+
+/// Rules for things that are usually safe to exclude, because they can be
+/// regenerated or downloaded again
+pub fn builtin_rules() -> Vec<ExclusionRule> {
+	vec![
+		ExclusionRule::new("node_modules", "**/node_modules"),
+		ExclusionRule::new("Build output", "**/target"),
+		ExclusionRule::new("Caches", "*/Users/*/Library/Caches"),
+		ExclusionRule::new("Docker disk image", "**/Docker.raw"),
+		ExclusionRule::new("Trash", "**/.Trash"),
+		ExclusionRule::new(
+			"Xcode DerivedData",
+			"**/Library/Developer/Xcode/DerivedData",
+		),
+		ExclusionRule::new("Virtual machine", "**/*.vmwarevm"),
+		ExclusionRule::new("Virtual machine", "**/*.pvm"),
+		ExclusionRule::new("Virtual machine", "**/*.utm"),
+	]
+}
+
+/// Converts a glob to an anchored regex
+pub fn glob_to_regex(glob: &str) -> String {
+	let mut regex = String::from("^");
+	let mut rest = glob;
+	while let Some(c) = rest.chars().next() {
+		if let Some(after) = rest.strip_prefix("**/") {
+			regex.push_str("(?:.*/)?");
+			rest = after;
+			continue;
+		}
+		if let Some(after) = rest.strip_prefix("**") {
+			regex.push_str(".*");
+			rest = after;
+			continue;
+		}
+		match c {
+			'*' => regex.push_str("[^/]*"),
+			'?' => regex.push_str("[^/]"),
+			c => regex.push_str(&regex::escape(&c.to_string())),
+		}
+		rest = &rest[c.len_utf8()..];
+	}
+	regex.push('$');
+	regex
+}
+
+struct CompiledRule {
+	name: String,
+	regex: Regex,
+}
+
+fn compile(rules: &[ExclusionRule]) -> Result<Vec<CompiledRule>, Error> {
+	let mut compiled = Vec::new();
+	for rule in rules {
+		let regex = match Regex::new(&glob_to_regex(&rule.pattern)) {
+			Ok(regex) => regex,
+			Err(e) => {
+				let message = format!("Invalid exclusion rule {}: {}", rule.pattern, e);
+				return Err(Error::parse_value(message, &rule.pattern));
+			}
+		};
+		compiled.push(CompiledRule {
+			name: rule.name.clone(),
+			regex,
+		});
+	}
+	Ok(compiled)
+}
+
+/// A changed path that matches an exclusion rule
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct Suggestion {
+	/// Path relative to the backups
+	pub path: String,
+	/// Path on the backed up disk, which is what gets excluded
+	pub source_path: String,
+	/// Name of the rule that matched
+	pub rule: String,
+	pub is_file: bool,
+	/// Bytes added or updated in the backup
+	#[specta(type = u32)] // tauri bigint fix
+	pub size: u64,
+	/// Whether the path is already excluded from backups
+	pub excluded: bool,
+}
+
+/// Calls `f` with the path relative to the backup, the full path and the
+/// item of each changed path in a comparison, parents first. The children of
+/// a path are skipped when `f` returns `false`.
+fn walk(
+	dir_map: &DirMap,
+	backup_path: &str,
+	mut f: impl FnMut(&str, &str, &LoadedBackupItem) -> bool,
+) {
+	let mut stack = vec![(backup_path.trim_end_matches('/').to_string(), String::new())];
+	while let Some((dir, relative_dir)) = stack.pop() {
+		let contents = match dir_map.map.get(&dir) {
+			Some(contents) => contents,
+			None => continue,
+		};
+		for (name, item) in contents {
+			let path = match relative_dir.as_str() {
+				"" => name.clone(),
+				relative_dir => format!("{}/{}", relative_dir, name),
+			};
+			let full_path = join_path(&dir, name);
+			if f(&path, &full_path, item) {
+				stack.push((full_path, path));
+			}
+		}
+	}
+}
+
+/// Finds the changed paths in a comparison that match a rule, largest first.
+/// Paths inside a match aren't checked, so a `node_modules` inside another
+/// one isn't suggested separately.
+pub fn suggest(
+	dir_map: &DirMap,
+	backup_path: &str,
+	rules: &[ExclusionRule],
+) -> Result<Vec<Suggestion>, Error> {
+	let rules = compile(rules)?;
+	let mut suggestions = Vec::new();
+	walk(dir_map, backup_path, |path, _, item| {
+		if item.size == 0 {
+			return false;
+		}
+		match rules.iter().find(|rule| rule.regex.is_match(path)) {
+			Some(rule) => {
+				suggestions.push(Suggestion {
+					path: path.to_string(),
+					source_path: source_path(path),
+					rule: rule.name.clone(),
+					is_file: item.change.is_some(),
+					size: item.size,
+					excluded: false,
+				});
+				false
+			}
+			None => true,
+		}
+	});
+	suggestions.sort_by(|a, b| b.size.cmp(&a.size).then(a.path.cmp(&b.path)));
+	Ok(suggestions)
+}
+
+/// Where a path relative to the backups is on disk. The first component is
+/// the volume name. The data volume of the startup disk ("Macintosh HD -
+/// Data") is mounted at `/`, and other volumes are in `/Volumes`.
+pub fn source_path(path: &str) -> String {
+	let path = path.trim_matches('/');
+	let (volume, rest) = path.split_once('/').unwrap_or((path, ""));
+	let root = if volume.ends_with(" - Data") {
+		"/".to_string()
+	} else {
+		format!("/Volumes/{}", volume)
+	};
+	if rest.is_empty() {
+		root
+	} else {
+		join_path(&root, rest)
+	}
+}
+
+pub const PREFERENCES_PATH: &str = "/Library/Preferences/com.apple.TimeMachine.plist";
+
+#[derive(Serialize, Clone, Copy, Type, Debug, PartialEq)]
+pub enum ExclusionSource {
+	/// Listed in the Time Machine preferences, which is where exclusions
+	/// added in System Settings go
+	Preferences,
+	/// Set on the item itself, for example by `tmutil addexclusion`
+	Sticky,
+}
+
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct Exclusion {
+	/// Path on disk
+	pub path: String,
+	pub source: ExclusionSource,
+}
+
+#[derive(Deserialize)]
+struct PreferencesXml {
+	#[serde(rename = "SkipPaths", default)]
+	skip_paths: Vec<String>,
+	#[serde(rename = "ExcludeByPath", default)]
+	exclude_by_path: Vec<String>,
+}
+
+/// Paths that are excluded from backups
+#[derive(Serialize, Clone, Type, Debug, Default)]
+pub struct Exclusions {
+	pub exclusions: Vec<Exclusion>,
+}
+
+impl Exclusions {
+	/// Reads the exclusions in the Time Machine preferences. `~` is expanded
+	/// using `home`.
+	pub fn from_preferences(bytes: &[u8], home: Option<&str>) -> Result<Self, Error> {
+		let preferences: PreferencesXml = match plist::from_bytes(bytes) {
+			Ok(preferences) => preferences,
+			Err(e) => {
+				let message = format!("Unable to parse Time Machine preferences: {}", e);
+				return Err(Error::parse(message));
+			}
+		};
+		let paths = preferences.skip_paths.into_iter();
+		let paths = paths.chain(preferences.exclude_by_path);
+		let exclusions = paths
+			.map(|path| match (path.strip_prefix('~'), home) {
+				(Some(rest), Some(home)) => format!("{}{}", home.trim_end_matches('/'), rest),
+				_ => path,
+			})
+			.map(|path| Exclusion {
+				path,
+				source: ExclusionSource::Preferences,
+			})
+			.collect();
+		Ok(Self { exclusions })
+	}
+	/// Adds the excluded paths in the output of `tmutil isexcluded`, which
+	/// has lines like `[Excluded]    /path`
+	pub fn add_sticky(&mut self, isexcluded_output: &str) {
+		for line in isexcluded_output.lines() {
+			if let Some(path) = line.strip_prefix("[Excluded]") {
+				let path = path.trim();
+				if self.find(path).is_none() {
+					self.exclusions.push(Exclusion {
+						path: path.to_string(),
+						source: ExclusionSource::Sticky,
+					});
+				}
+			}
+		}
+	}
+	/// The exclusion of a path on disk, or of a directory it's in
+	pub fn find(&self, path: &str) -> Option<&Exclusion> {
+		self.exclusions.iter().find(|exclusion| {
+			let dir = exclusion.path.trim_end_matches('/');
+			match path.strip_prefix(dir) {
+				Some(rest) => rest.is_empty() || rest.starts_with('/') || dir.is_empty(),
+				None => false,
+			}
+		})
+	}
+	pub fn mark(&self, suggestions: &mut [Suggestion]) {
+		for suggestion in suggestions {
+			suggestion.excluded = self.find(&suggestion.source_path).is_some();
+		}
+	}
+	/// Full paths of the changed items in a comparison that are excluded.
+	/// Items inside an excluded directory aren't included.
+	pub fn excluded_paths(&self, dir_map: &DirMap, backup_path: &str) -> Vec<String> {
+		let mut excluded = Vec::new();
+		walk(dir_map, backup_path, |path, full_path, _| {
+			if self.find(&source_path(path)).is_some() {
+				excluded.push(full_path.to_string());
+				return false;
+			}
+			true
+		});
+		excluded
+	}
+}
+
+/// Reads the exclusions in the Time Machine preferences, and checks which of
+/// `paths` have sticky exclusions. Paths that don't exist on this computer
+/// are skipped. A missing preferences file means there are no exclusions.
+pub fn load_exclusions(
+	backend: &dyn TmutilBackend,
+	preferences_path: &Path,
+	paths: &[String],
+) -> Result<Exclusions, Error> {
+	let mut exclusions = match fs::read(preferences_path) {
+		Ok(bytes) => {
+			let home = std::env::var("HOME").ok();
+			Exclusions::from_preferences(&bytes, home.as_deref())?
+		}
+		Err(e) if e.kind() == io::ErrorKind::NotFound => Exclusions::default(),
+		Err(e) => return Err(Error::io("Unable to read", preferences_path, e)),
+	};
+	let existing: Vec<_> = paths
+		.iter()
+		.filter(|path| Path::new(path).exists())
+		.map(|path| path.as_str())
+		.collect();
+	// keep the command line short
+	for chunk in existing.chunks(100) {
+		let output = backend.isexcluded(chunk)?;
+		exclusions.add_sticky(&output);
+	}
+	Ok(exclusions)
+}
+
+/// Paths on disk of the changed directories in a comparison, which are
+/// checked for sticky exclusions
+pub fn changed_dirs(dir_map: &DirMap, backup_path: &str) -> Vec<String> {
+	let mut dirs = Vec::new();
+	walk(dir_map, backup_path, |path, _, item| {
+		if item.change.is_none() {
+			dirs.push(source_path(path));
+		}
+		true
+	});
+	dirs
+}
+
+#[test]
+fn test_glob_to_regex() {
+	let matches = |glob: &str, path: &str| Regex::new(&glob_to_regex(glob)).unwrap().is_match(path);
+	assert!(matches("**/node_modules", "node_modules"));
+	assert!(matches("**/node_modules", "a/b/node_modules"));
+	assert!(!matches("**/node_modules", "a/node_modules/b"));
+	assert!(!matches("**/node_modules", "a/my_node_modules"));
+	assert!(matches(
+		"*/Users/*/Library/Caches",
+		"HD/Users/alice/Library/Caches"
+	));
+	assert!(!matches(
+		"*/Users/*/Library/Caches",
+		"HD/Users/a/b/Library/Caches"
+	));
+	assert!(matches("**/*.utm", "x/Linux.utm"));
+	assert!(matches("a?c", "abc"));
+	assert!(!matches("a.c", "abc"));
+	assert!(matches("a/**", "a/b/c"));
+}
+
+#[test]
+fn test_suggest() {
+	use crate::compare::{self, CancelToken};
+	use crate::test_util::{fixture_backend, fixture_backups};
+
+	assert_eq!(source_path("Macintosh HD - Data/Users/a"), "/Users/a");
+	assert_eq!(source_path("Macintosh HD - Data"), "/");
+	assert_eq!(source_path("External/Photos"), "/Volumes/External/Photos");
+
+	let backend = fixture_backend();
+	let backups = fixture_backups(&backend);
+	let (old, new) = (&backups[0].path, &backups[1].path);
+	let dir_map = compare::compare(&backend, old, new, &CancelToken::new(), &mut |_| {}).unwrap();
+
+	let mut rules = builtin_rules();
+	rules.push(ExclusionRule::new("Documents", "**/Documents/*.pdf"));
+	let suggestions = suggest(&dir_map, new, &rules).unwrap();
+	let found: Vec<_> = suggestions
+		.iter()
+		.map(|s| (s.rule.as_str(), s.source_path.as_str()))
+		.collect();
+	assert_eq!(
+		found,
+		[
+			(
+				"Docker disk image",
+				"/Users/alice/Library/Containers/com.docker.docker/Data/vms/0/data/Docker.raw"
+			),
+			("Documents", "/Users/alice/Documents/report.pdf"),
+			("node_modules", "/Users/alice/Projects/app/node_modules"),
+		]
+	);
+	assert!(suggestions[0].is_file);
+	assert_eq!(suggestions[2].size, 2048 + 612);
+
+	let mut exclusions = Exclusions::default();
+	exclusions.add_sticky("[Excluded]    /Users/alice/Projects/app/node_modules");
+	let mut suggestions = suggestions;
+	exclusions.mark(&mut suggestions);
+	let excluded: Vec<_> = suggestions.iter().map(|s| s.excluded).collect();
+	assert_eq!(excluded, [false, false, true]);
+	let excluded_paths = exclusions.excluded_paths(&dir_map, new);
+	assert_eq!(
+		excluded_paths,
+		[format!(
+			"{}/Macintosh HD - Data/Users/alice/Projects/app/node_modules",
+			new
+		)]
+	);
+	let dirs = changed_dirs(&dir_map, new);
+	assert!(dirs.contains(&"/Users/alice/Projects/app/node_modules".to_string()));
+	assert!(!dirs.iter().any(|dir| dir.ends_with("report.pdf")));
+}
+
+#[test]
+fn test_exclusions() {
+	let preferences = br#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>AutoBackup</key>
+	<true/>
+	<key>SkipPaths</key>
+	<array>
+		<string>~/Downloads</string>
+	</array>
+	<key>ExcludeByPath</key>
+	<array>
+		<string>/Users/alice/Projects/app/node_modules</string>
+	</array>
+</dict>
+</plist>"#;
+	let mut exclusions = Exclusions::from_preferences(preferences, Some("/Users/alice/")).unwrap();
+	assert_eq!(exclusions.exclusions[0].path, "/Users/alice/Downloads");
+	assert!(exclusions.find("/Users/alice/Downloads/x.dmg").is_some());
+	assert!(exclusions.find("/Users/alice/Downloads2").is_none());
+	assert!(exclusions.find("/Users/alice").is_none());
+
+	exclusions.add_sticky(
+		"[Excluded]    /Users/alice/Library/Containers/com.docker.docker\n\
+		 [Included]    /Users/alice/Projects/app/target\n",
+	);
+	let docker = exclusions.find("/Users/alice/Library/Containers/com.docker.docker/Data");
+	assert_eq!(docker.unwrap().source, ExclusionSource::Sticky);
+	assert!(exclusions
+		.find("/Users/alice/Projects/app/target")
+		.is_none());
+	assert!(Exclusions::from_preferences(b"nope", None).is_err());
+
+	// only paths that exist are checked
+	let backend = crate::test_util::fixture_backend();
+	let missing = Path::new("/nonexistent/com.apple.TimeMachine.plist");
+	let paths = ["/nonexistent".to_string()];
+	let exclusions = load_exclusions(&backend, missing, &paths).unwrap();
+	assert!(exclusions.exclusions.is_empty());
+	let paths = [env!("CARGO_MANIFEST_DIR").to_string()];
+	let exclusions = load_exclusions(&backend, missing, &paths).unwrap();
+	assert_eq!(exclusions.exclusions.len(), 1);
+}
@@ -0,0 +1,203 @@
+use crate::compare::{Difference, Totals};
+use crate::dir_map::{join_path, ChangeKind, DirMap};
+use crate::error::Error;
+use crate::html_report;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use specta::Type;
+use std::io::Write;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Type, Debug, PartialEq)]
+pub enum ExportFormat {
+	/// One row per changed item, with its path, kind and size
+	Csv,
+	/// The totals, the changed items and the whole `DirMap`
+	Json,
+	/// ncdu's JSON export format, which can be browsed with `ncdu -f`
+	Ncdu,
+	/// A standalone HTML report, see `html_report`
+	Html,
+}
+
+impl ExportFormat {
+	pub fn from_name(name: &str) -> Option<Self> {
+		match name {
+			"csv" => Some(Self::Csv),
+			"json" => Some(Self::Json),
+			"ncdu" => Some(Self::Ncdu),
+			"html" => Some(Self::Html),
+			_ => None,
+		}
+	}
+	/// File extension, without the dot
+	pub fn extension(self) -> &'static str {
+		match self {
+			Self::Csv => "csv",
+			Self::Json | Self::Ncdu => "json",
+			Self::Html => "html",
+		}
+	}
+}
+
+/// A changed item, for the list of changes in exports
+#[derive(Serialize)]
+struct ExportedChange<'a> {
+	path: String,
+	kind: ChangeKind,
+	size: u64,
+	old_size: Option<u64>,
+	new_size: Option<u64>,
+	differences: &'a [Difference],
+}
+
+#[derive(Serialize)]
+struct ExportedComparison<'a> {
+	backup: &'a str,
+	totals: &'a Totals,
+	changes: Vec<ExportedChange<'a>>,
+	map: &'a DirMap,
+}
+
+fn changes(dir_map: &DirMap) -> Vec<ExportedChange<'_>> {
+	let mut changes: Vec<_> = dir_map
+		.changed_items()
+		.filter_map(|(path, item)| {
+			let change = item.change.as_ref()?;
+			let size = match change.kind {
+				ChangeKind::Removed => item.removed_size,
+				_ => item.size,
+			};
+			Some(ExportedChange {
+				path,
+				kind: change.kind,
+				size,
+				old_size: change.old_size,
+				new_size: change.new_size,
+				differences: &change.differences,
+			})
+		})
+		.collect();
+	changes.sort_by(|a, b| a.path.cmp(&b.path));
+	changes
+}
+
+fn write_error(e: impl std::fmt::Display) -> Error {
+	Error::other(format!("Unable to write export: {}", e))
+}
+
+/// Writes the comparison of `backup_path` and the backup before it
+pub fn export(
+	dir_map: &DirMap,
+	backup_path: &str,
+	format: ExportFormat,
+	mut writer: impl Write,
+) -> Result<(), Error> {
+	match format {
+		ExportFormat::Csv => write_csv(dir_map, &mut writer),
+		ExportFormat::Json => {
+			let comparison = ExportedComparison {
+				backup: backup_path,
+				totals: &dir_map.totals,
+				changes: changes(dir_map),
+				map: dir_map,
+			};
+			serde_json::to_writer_pretty(&mut writer, &comparison).map_err(write_error)
+		}
+		ExportFormat::Ncdu => {
+			let export = ncdu_export(dir_map, backup_path);
+			serde_json::to_writer(&mut writer, &export).map_err(write_error)
+		}
+		ExportFormat::Html => html_report::write_html(dir_map, backup_path, &mut writer),
+	}?;
+	writer.flush().map_err(write_error)
+}
+
+fn csv_field(field: &str) -> String {
+	if field.contains(['"', ',', '\n', '\r']) {
+		format!("\"{}\"", field.replace('"', "\"\""))
+	} else {
+		field.to_string()
+	}
+}
+
+fn write_csv(dir_map: &DirMap, writer: &mut impl Write) -> Result<(), Error> {
+	writeln!(writer, "path,kind,size").map_err(write_error)?;
+	for change in changes(dir_map) {
+		let kind = format!("{:?}", change.kind);
+		let path = csv_field(&change.path);
+		writeln!(writer, "{},{},{}", path, kind, change.size).map_err(write_error)?;
+	}
+	Ok(())
+}
+
+/// Builds an ncdu export of the bytes added or updated in the backup. Items
+/// that were only removed are left out, since they take no space in it.
+fn ncdu_export(dir_map: &DirMap, backup_path: &str) -> Value {
+	let backup_path = backup_path.trim_end_matches('/');
+	let root = ncdu_dir(dir_map, backup_path, backup_path);
+	json!([1, 2, { "progname": "tmi", "progver": env!("CARGO_PKG_VERSION") }, root])
+}
+
+fn ncdu_dir(dir_map: &DirMap, path: &str, name: &str) -> Value {
+	let mut entries = vec![json!({ "name": name })];
+	if let Some(contents) = dir_map.map.get(path) {
+		let mut names: Vec<_> = contents.keys().collect();
+		names.sort();
+		for child_name in names {
+			let item = &contents[child_name];
+			if item.size == 0 {
+				continue;
+			}
+			let child_path = join_path(path, child_name);
+			if dir_map.map.contains_key(&child_path) {
+				entries.push(ncdu_dir(dir_map, &child_path, child_name));
+			} else {
+				entries.push(json!({
+					"name": child_name,
+					"asize": item.size,
+					"dsize": item.size,
+				}));
+			}
+		}
+	}
+	Value::Array(entries)
+}
+
+#[test]
+fn test_export() {
+	use crate::test_util::{add, delete};
+
+	let mut dir_map = DirMap::new();
+	dir_map.add_change(add("/b/2/a, \"quoted\"", 5)).unwrap();
+	dir_map.add_change(add("/b/2/dir/file", 7)).unwrap();
+	dir_map.add_change(delete("/b/2/dir/old", 3)).unwrap();
+
+	let mut csv = Vec::new();
+	export(&dir_map, "/b/2", ExportFormat::Csv, &mut csv).unwrap();
+	assert_eq!(
+		String::from_utf8(csv).unwrap(),
+		"path,kind,size\n\
+		\"/b/2/a, \"\"quoted\"\"\",Added,5\n\
+		/b/2/dir/file,Added,7\n\
+		/b/2/dir/old,Removed,3\n"
+	);
+
+	let mut json = Vec::new();
+	export(&dir_map, "/b/2", ExportFormat::Json, &mut json).unwrap();
+	let json: Value = serde_json::from_slice(&json).unwrap();
+	assert_eq!(json["changes"].as_array().unwrap().len(), 3);
+	assert_eq!(json["map"]["map"]["/b/2"]["dir"]["size"], 7);
+
+	let mut ncdu = Vec::new();
+	export(&dir_map, "/b/2/", ExportFormat::Ncdu, &mut ncdu).unwrap();
+	let ncdu: Value = serde_json::from_slice(&ncdu).unwrap();
+	assert_eq!(ncdu[0], 1);
+	assert_eq!(
+		ncdu[3],
+		json!([
+			{ "name": "/b/2" },
+			{ "name": "a, \"quoted\"", "asize": 5, "dsize": 5 },
+			[{ "name": "dir" }, { "name": "file", "asize": 7, "dsize": 7 }],
+		])
+	);
+}
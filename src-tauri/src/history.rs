@@ -0,0 +1,521 @@
+use crate::compare::{Change, Totals};
+use crate::dir_map::{join_path, ChangeKind, DirMap, LoadedBackupItem};
+use crate::error::{Error, ErrorKind};
+use crate::listbackups::Backup;
+use crate::snapshot;
+use serde::Serialize;
+use specta::Type;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+/// How a destination grew, with a point for every backup
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct Timeline {
+	pub points: Vec<TimelinePoint>,
+	/// Largest `added_size + changed_size` of a point, for scaling a chart
+	#[specta(type = u32)] // tauri bigint fix
+	pub max_size: u64,
+	/// Number of backups after the first whose comparison isn't available
+	pub missing: u32,
+}
+
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct TimelinePoint {
+	pub path: String,
+	pub name: String,
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub timestamp: Option<u64>,
+	/// Changes since the previous backup. `None` for the first backup, and
+	/// when the comparison isn't available
+	pub totals: Option<Totals>,
+	/// Bytes added and changed in this and the earlier backups
+	#[specta(type = u32)] // tauri bigint fix
+	pub running_total: u64,
+}
+
+/// Builds a timeline from the totals of each pair of adjacent backups.
+/// `get_totals` is called with the old and new backup, and returns `None`
+/// if the comparison isn't available.
+pub fn timeline(
+	backups: &[Backup],
+	mut get_totals: impl FnMut(&Backup, &Backup) -> Option<Totals>,
+) -> Timeline {
+	let mut timeline = Timeline {
+		points: Vec::with_capacity(backups.len()),
+		max_size: 0,
+		missing: 0,
+	};
+	let mut running_total = 0;
+	for (i, backup) in backups.iter().enumerate() {
+		let totals = match i.checked_sub(1) {
+			Some(prev) => get_totals(&backups[prev], backup),
+			None => None,
+		};
+		match &totals {
+			Some(totals) => {
+				let size = totals.added_size + totals.changed_size;
+				running_total += size;
+				timeline.max_size = timeline.max_size.max(size);
+			}
+			None if i > 0 => timeline.missing += 1,
+			None => {}
+		}
+		timeline.points.push(TimelinePoint {
+			path: backup.path.clone(),
+			name: backup.name.clone(),
+			timestamp: backup.timestamp,
+			totals,
+			running_total,
+		});
+	}
+	timeline
+}
+
+/// The backups from `from` to `to`, including both
+pub fn backup_range<'a>(
+	backups: &'a [Backup],
+	from: &str,
+	to: &str,
+) -> Result<&'a [Backup], Error> {
+	let position = |path: &str| match backups.iter().position(|b| b.path == path) {
+		Some(pos) => Ok(pos),
+		None => Err(Error::new(
+			ErrorKind::BackupNotFound {
+				path: path.to_string(),
+			},
+			format!("Unable to find backup {}", path),
+		)),
+	};
+	let from_pos = position(from)?;
+	let to_pos = position(to)?;
+	if from_pos >= to_pos {
+		let kind = ErrorKind::BackupOrder {
+			old: from.to_string(),
+			new: to.to_string(),
+		};
+		return Err(Error::new(
+			kind,
+			format!("{} is not older than {}", from, to),
+		));
+	}
+	Ok(&backups[from_pos..=to_pos])
+}
+
+/// Full path of `path` in a backup. `path` is relative to the backup, like
+/// "Macintosh HD - Data/Users"
+pub fn path_in_backup(backup_path: &str, path: &str) -> String {
+	let path = path.trim_matches('/');
+	if path.is_empty() {
+		return backup_path.to_string();
+	}
+	join_path(backup_path.trim_end_matches('/'), path)
+}
+
+/// Changes at or below a path, for each backup in a range
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct PathHistory {
+	/// Path relative to the backups
+	pub path: String,
+	pub points: Vec<PathHistoryPoint>,
+	/// Number of backups that changed something at or below the path
+	pub changed_count: u32,
+	/// Bytes added or updated at or below the path in all the backups
+	#[specta(type = u32)] // tauri bigint fix
+	pub total_size: u64,
+}
+
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct PathHistoryPoint {
+	pub backup: String,
+	pub name: String,
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub timestamp: Option<u64>,
+	/// Changes since the previous backup. Empty if nothing changed.
+	pub item: LoadedBackupItem,
+}
+
+impl PathHistory {
+	pub fn new(path: &str) -> Self {
+		Self {
+			path: path.to_string(),
+			points: Vec::new(),
+			changed_count: 0,
+			total_size: 0,
+		}
+	}
+	/// Adds the comparison of `new` and the backup before it
+	pub fn add(&mut self, new: &Backup, dir_map: &DirMap) {
+		let full_path = path_in_backup(&new.path, &self.path);
+		let item = dir_map.get_item(&full_path).cloned().unwrap_or_default();
+		if item.size > 0 || item.removed_count > 0 || item.change.is_some() {
+			self.changed_count += 1;
+		}
+		self.total_size += item.size;
+		self.points.push(PathHistoryPoint {
+			backup: new.path.clone(),
+			name: new.name.clone(),
+			timestamp: new.timestamp,
+			item,
+		});
+	}
+}
+
+/// Paths that change in many backups
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct ChurnReport {
+	/// Number of comparisons analyzed
+	pub comparisons: u32,
+	/// Most frequently changed first, then largest first
+	pub churners: Vec<Churner>,
+}
+
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct Churner {
+	/// Path relative to the backups
+	pub path: String,
+	pub is_file: bool,
+	/// Number of backups that changed something at or below the path
+	pub count: u32,
+	/// `count` divided by the number of comparisons
+	pub frequency: f64,
+	/// Bytes added or updated in all the backups
+	#[specta(type = u32)] // tauri bigint fix
+	pub total_size: u64,
+	/// Most bytes added or updated in one backup
+	#[specta(type = u32)] // tauri bigint fix
+	pub max_size: u64,
+}
+
+#[derive(Default)]
+struct ChurnStats {
+	count: u32,
+	total_size: u64,
+	max_size: u64,
+	is_file: bool,
+}
+
+/// Counts how often each path changes across comparisons
+#[derive(Default)]
+pub struct ChurnAnalysis {
+	comparisons: u32,
+	paths: HashMap<String, ChurnStats>,
+}
+
+impl ChurnAnalysis {
+	pub fn new() -> Self {
+		Self::default()
+	}
+	/// Adds the comparison of `new` and the backup before it
+	pub fn add(&mut self, new: &Backup, dir_map: &DirMap) {
+		self.comparisons += 1;
+		let prefix = format!("{}/", new.path.trim_end_matches('/'));
+		for (dir, contents) in &dir_map.map {
+			for (name, item) in contents {
+				let full_path = join_path(dir, name);
+				// skip the directories the backup is in
+				let path = match full_path.strip_prefix(&prefix) {
+					Some(path) => path,
+					None => continue,
+				};
+				if item.size == 0 && item.removed_count == 0 && item.change.is_none() {
+					continue;
+				}
+				let stats = self.paths.entry(path.to_string()).or_default();
+				stats.count += 1;
+				stats.total_size += item.size;
+				stats.max_size = stats.max_size.max(item.size);
+				stats.is_file = item.change.is_some();
+			}
+		}
+	}
+	/// Paths that changed in at least `min_frequency` of the comparisons,
+	/// from 0 to 1. A directory is left out when one of its children changed
+	/// just as often and as much, so only the deepest of a chain of
+	/// directories is included.
+	pub fn report(&self, min_frequency: f64, limit: usize) -> ChurnReport {
+		let comparisons = self.comparisons.max(1) as f64;
+		let frequent: Vec<_> = self
+			.paths
+			.iter()
+			.filter(|(_, stats)| stats.count as f64 / comparisons >= min_frequency)
+			.collect();
+
+		let mut children = HashSet::new();
+		for (path, stats) in &frequent {
+			let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+			children.insert((parent, stats.count, stats.total_size));
+		}
+		let mut churners: Vec<_> = frequent
+			.into_iter()
+			.filter(|(path, stats)| {
+				let key = (path.as_str(), stats.count, stats.total_size);
+				stats.is_file || !children.contains(&key)
+			})
+			.map(|(path, stats)| Churner {
+				path: path.clone(),
+				is_file: stats.is_file,
+				count: stats.count,
+				frequency: stats.count as f64 / comparisons,
+				total_size: stats.total_size,
+				max_size: stats.max_size,
+			})
+			.collect();
+		churners.sort_by(|a, b| {
+			b.count
+				.cmp(&a.count)
+				.then(b.total_size.cmp(&a.total_size))
+				.then(a.path.cmp(&b.path))
+		});
+		churners.truncate(limit);
+		ChurnReport {
+			comparisons: self.comparisons,
+			churners,
+		}
+	}
+}
+
+/// A backup where a path was added, updated or removed
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct PathVersion {
+	pub backup: String,
+	pub name: String,
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub timestamp: Option<u64>,
+	pub kind: ChangeKind,
+	/// Size in the backup before, for updated and removed paths. For
+	/// directories found in comparisons, the bytes removed below it.
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub old_size: Option<u64>,
+	/// Size in this backup, for added and updated paths. For directories found
+	/// in comparisons, the bytes added or updated below it.
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub new_size: Option<u64>,
+	/// Whether this was found in a comparison rather than the backup directories
+	pub from_comparison: bool,
+}
+
+/// The backups where a path changed, to find the one to restore it from
+#[derive(Serialize, Clone, Type, Debug)]
+pub struct PathVersions {
+	/// Path relative to the backups
+	pub path: String,
+	/// Oldest first
+	pub versions: Vec<PathVersion>,
+	/// Names of the backups that couldn't be checked, because there was no
+	/// comparison and the backup directories couldn't be read
+	pub unchecked: Vec<String>,
+}
+
+impl PathVersions {
+	pub fn new(path: &str) -> Self {
+		Self {
+			path: path.to_string(),
+			versions: Vec::new(),
+			unchecked: Vec::new(),
+		}
+	}
+	fn push(
+		&mut self,
+		new: &Backup,
+		kind: ChangeKind,
+		sizes: (Option<u64>, Option<u64>),
+		from_comparison: bool,
+	) {
+		self.versions.push(PathVersion {
+			backup: new.path.clone(),
+			name: new.name.clone(),
+			timestamp: new.timestamp,
+			kind,
+			old_size: sizes.0,
+			new_size: sizes.1,
+			from_comparison,
+		});
+	}
+	/// Adds the change of the path in the comparison of `new` and the backup
+	/// before it
+	pub fn add_comparison(&mut self, new: &Backup, dir_map: &DirMap) {
+		let full_path = path_in_backup(&new.path, &self.path);
+		let item = match dir_map.get_item(&full_path) {
+			Some(item) => item,
+			None => return,
+		};
+		match &item.change {
+			Some(change) => {
+				let sizes = (change.old_size, change.new_size);
+				self.push(new, change.kind, sizes, true);
+			}
+			None => {
+				let removed_size = Some(item.removed_size).filter(|size| *size > 0);
+				let sizes = (removed_size, Some(item.size));
+				self.push(new, ChangeKind::Updated, sizes, true);
+			}
+		}
+	}
+	/// Adds the change of the path between the backup directories of `old` and
+	/// `new`, see `snapshot::compare_path`. `old` is `None` for the first
+	/// backup.
+	pub fn add_directories(&mut self, old: Option<&Backup>, new: &Backup) -> Result<(), Error> {
+		for backup in old.iter().chain([&new]) {
+			if !Path::new(&backup.path).is_dir() {
+				let message = format!("Unable to read backup {}", backup.path);
+				let path = backup.path.clone();
+				return Err(Error::new(ErrorKind::Io { path }, message));
+			}
+		}
+		let old_path = old.map(|old| path_in_backup(&old.path, &self.path));
+		let new_path = path_in_backup(&new.path, &self.path);
+		let change =
+			snapshot::compare_path(old_path.as_ref().map(Path::new), Path::new(&new_path))?;
+		match change {
+			Some(Change::Add(add)) => self.push(
+				new,
+				ChangeKind::Added,
+				(None, Some(add.added_item.size)),
+				false,
+			),
+			Some(Change::Update(update)) => {
+				let sizes = (Some(update.older_item.size), Some(update.newer_item.size));
+				self.push(new, ChangeKind::Updated, sizes, false)
+			}
+			Some(Change::Delete(delete)) => {
+				let sizes = (Some(delete.removed_item.size), None);
+				self.push(new, ChangeKind::Removed, sizes, false)
+			}
+			None => {}
+		}
+		Ok(())
+	}
+	pub fn add_unchecked(&mut self, new: &Backup) {
+		self.unchecked.push(new.name.clone());
+	}
+}
+
+#[test]
+fn test_timeline() {
+	let backup = |name: &str| Backup {
+		path: format!("/b/{}", name),
+		name: name.to_string(),
+		timestamp: None,
+		interval: None,
+	};
+	let backups = [backup("1"), backup("2"), backup("3"), backup("4")];
+	let timeline = timeline(&backups, |old, new| match (&*old.name, &*new.name) {
+		("1", "2") => Some(Totals {
+			added_size: 10,
+			changed_size: 5,
+			removed_size: 1,
+		}),
+		("3", "4") => Some(Totals {
+			added_size: 2,
+			changed_size: 0,
+			removed_size: 20,
+		}),
+		_ => None,
+	});
+	let running_totals: Vec<_> = timeline.points.iter().map(|p| p.running_total).collect();
+	assert_eq!(running_totals, [0, 15, 15, 17]);
+	assert!(timeline.points[0].totals.is_none());
+	assert!(timeline.points[2].totals.is_none());
+	assert_eq!(timeline.max_size, 15);
+	assert_eq!(timeline.missing, 1);
+}
+
+#[test]
+fn test_range_analysis() {
+	use crate::compare::{self, CancelToken};
+	use crate::test_util::{fixture_backend, fixture_backups};
+
+	let backend = fixture_backend();
+	let backups = fixture_backups(&backend);
+	let range = backup_range(&backups, &backups[0].path, &backups[2].path).unwrap();
+	assert_eq!(range.len(), 3);
+	assert!(backup_range(&backups, &backups[1].path, &backups[0].path).is_err());
+	assert!(backup_range(&backups, "/nope", &backups[0].path).is_err());
+
+	let mut churn = ChurnAnalysis::new();
+	let mut docker = PathHistory::new("/Macintosh HD - Data/Users/alice/Library/Containers/");
+	let mut downloads = PathHistory::new("Macintosh HD - Data/Users/alice/Downloads");
+	let mut downloads_versions = PathVersions::new("Macintosh HD - Data/Users/alice/Downloads");
+	for pair in range.windows(2) {
+		let cancel = CancelToken::new();
+		let dir_map =
+			compare::compare(&backend, &pair[0].path, &pair[1].path, &cancel, &mut |_| {}).unwrap();
+		docker.add(&pair[1], &dir_map);
+		downloads.add(&pair[1], &dir_map);
+		downloads_versions.add_comparison(&pair[1], &dir_map);
+		churn.add(&pair[1], &dir_map);
+	}
+	assert_eq!(docker.points.len(), 2);
+	assert_eq!(docker.changed_count, 2);
+	assert!(docker.points.iter().all(|p| p.item.updated_count == 1));
+	assert_eq!(
+		docker.total_size,
+		docker.points[0].item.size + docker.points[1].item.size
+	);
+	// only removed in the first backup
+	assert_eq!(downloads.changed_count, 1);
+	assert_eq!(downloads.points[0].item.removed_count, 1);
+	assert_eq!(downloads.points[1].item.removed_count, 0);
+	assert_eq!(downloads_versions.versions.len(), 1);
+	assert_eq!(downloads_versions.versions[0].name, range[1].name);
+	assert!(downloads_versions.versions[0].old_size.unwrap() > 0);
+
+	let report = churn.report(1.0, 100);
+	assert_eq!(report.comparisons, 2);
+	let paths: Vec<_> = report.churners.iter().map(|c| c.path.as_str()).collect();
+	// "Macintosh HD - Data" and "Users" only contain "alice"
+	assert_eq!(paths[0], "Macintosh HD - Data/Users/alice");
+	let docker_raw = "Macintosh HD - Data/Users/alice/Library/Containers/com.docker.docker/Data/vms/0/data/Docker.raw";
+	assert!(paths.contains(&docker_raw));
+	assert!(!paths.contains(&"Macintosh HD - Data/Users/alice/Library"));
+	assert!(!paths.contains(&"Macintosh HD - Data/Users/alice/.zsh_history"));
+	assert!(report.churners.iter().all(|c| c.frequency == 1.0));
+	let report = churn.report(0.5, 100);
+	assert!(report
+		.churners
+		.iter()
+		.any(|c| c.path.ends_with(".zsh_history")));
+	assert_eq!(churn.report(0.0, 3).churners.len(), 3);
+}
+
+#[test]
+fn test_path_versions() {
+	use std::fs;
+
+	let root = crate::test_util::temp_dir("versions");
+	let backup = |name: &str| Backup {
+		path: root.join(name).to_string_lossy().to_string(),
+		name: name.to_string(),
+		timestamp: None,
+		interval: None,
+	};
+	let backups = [backup("1"), backup("2"), backup("3"), backup("4")];
+	for backup in &backups[..3] {
+		fs::create_dir_all(Path::new(&backup.path).join("Users")).unwrap();
+	}
+	let file = |backup: &Backup| Path::new(&backup.path).join("Users/notes.txt");
+	fs::write(file(&backups[0]), "one").unwrap();
+	fs::hard_link(file(&backups[0]), file(&backups[1])).unwrap();
+
+	let mut versions = PathVersions::new("Users/notes.txt");
+	for (i, new) in backups.iter().enumerate() {
+		let old = i.checked_sub(1).map(|i| &backups[i]);
+		if versions.add_directories(old, new).is_err() {
+			versions.add_unchecked(new);
+		}
+	}
+	fs::remove_dir_all(&root).unwrap();
+
+	let kinds: Vec<_> = versions
+		.versions
+		.iter()
+		.map(|v| (v.name.as_str(), v.kind))
+		.collect();
+	assert_eq!(
+		kinds,
+		[("1", ChangeKind::Added), ("3", ChangeKind::Removed)]
+	);
+	assert_eq!(versions.versions[0].new_size, Some(3));
+	assert_eq!(versions.versions[1].old_size, Some(3));
+	assert_eq!(versions.unchecked, ["4"]);
+}
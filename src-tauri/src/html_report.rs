@@ -0,0 +1,147 @@
+use crate::compare::Totals;
+use crate::dir_map::{join_path, ChangeKind, DirMap};
+use crate::error::Error;
+use serde::Serialize;
+use std::io::Write;
+
+const TEMPLATE: &str = include_str!("html_report.html");
+const DATA_PLACEHOLDER: &str = "{{REPORT_DATA}}";
+
+/// Number of items in the report's table of largest changes
+pub const TOP_COUNT: usize = 100;
+
+#[derive(Serialize)]
+struct ReportNode {
+	name: String,
+	size: u64,
+	removed_size: u64,
+	kind: Option<ChangeKind>,
+	/// `None` for files, so empty directories still open in the tree
+	children: Option<Vec<ReportNode>>,
+}
+
+#[derive(Serialize)]
+struct ReportItem {
+	path: String,
+	kind: ChangeKind,
+	size: u64,
+}
+
+#[derive(Serialize)]
+struct ReportData<'a> {
+	title: String,
+	backup: &'a str,
+	totals: &'a Totals,
+	top: Vec<ReportItem>,
+	tree: ReportNode,
+}
+
+fn report_node(dir_map: &DirMap, path: &str, name: &str) -> ReportNode {
+	let mut node = ReportNode {
+		name: name.to_string(),
+		size: 0,
+		removed_size: 0,
+		kind: None,
+		children: None,
+	};
+	if let Some(item) = dir_map.get_item(path) {
+		node.size = item.size;
+		node.removed_size = item.removed_size;
+		node.kind = item.change.as_ref().map(|change| change.kind);
+	}
+	if let Some(contents) = dir_map.map.get(path) {
+		let mut children: Vec<_> = contents
+			.keys()
+			.map(|child_name| report_node(dir_map, &join_path(path, child_name), child_name))
+			.collect();
+		children.sort_by(|a, b| {
+			(b.size + b.removed_size)
+				.cmp(&(a.size + a.removed_size))
+				.then_with(|| a.name.cmp(&b.name))
+		});
+		node.size = children.iter().map(|child| child.size).sum();
+		node.removed_size = children.iter().map(|child| child.removed_size).sum();
+		node.children = Some(children);
+	}
+	node
+}
+
+fn top_items(dir_map: &DirMap, count: usize) -> Vec<ReportItem> {
+	let mut items: Vec<_> = dir_map
+		.changed_items()
+		.filter_map(|(path, item)| {
+			let kind = item.change.as_ref()?.kind;
+			let size = match kind {
+				ChangeKind::Removed => item.removed_size,
+				_ => item.size,
+			};
+			Some(ReportItem { path, kind, size })
+		})
+		.collect();
+	items.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+	items.truncate(count);
+	items
+}
+
+/// Writes a standalone HTML page with the totals, the largest changes, a
+/// treemap and a collapsible tree of the comparison. All data is embedded, so
+/// the file can be shared and opened without the app.
+pub fn write_html(
+	dir_map: &DirMap,
+	backup_path: &str,
+	mut writer: impl Write,
+) -> Result<(), Error> {
+	let backup_path = backup_path.trim_end_matches('/');
+	let name = backup_path.rsplit('/').next().unwrap_or(backup_path);
+	let data = ReportData {
+		title: format!("Changes in backup {}", name),
+		backup: backup_path,
+		totals: &dir_map.totals,
+		top: top_items(dir_map, TOP_COUNT),
+		tree: report_node(dir_map, backup_path, name),
+	};
+	let json = serde_json::to_string(&data).map_err(write_error)?;
+	// Keeps paths like "</script>" from ending the script element
+	let json = json.replace('<', "\\u003c");
+	let (head, tail) = match TEMPLATE.split_once(DATA_PLACEHOLDER) {
+		Some(parts) => parts,
+		None => {
+			return Err(Error::other(
+				"Missing data placeholder in the report template",
+			))
+		}
+	};
+	writer.write_all(head.as_bytes()).map_err(write_error)?;
+	writer.write_all(json.as_bytes()).map_err(write_error)?;
+	writer.write_all(tail.as_bytes()).map_err(write_error)?;
+	writer.flush().map_err(write_error)
+}
+
+fn write_error(e: impl std::fmt::Display) -> Error {
+	Error::other(format!("Unable to write report: {}", e))
+}
+
+#[test]
+fn test_write_html() {
+	use crate::test_util::{add, delete};
+
+	let mut dir_map = DirMap::new();
+	dir_map.add_change(add("/b/2/small", 5)).unwrap();
+	dir_map.add_change(add("/b/2/dir/</script>", 7)).unwrap();
+	dir_map.add_change(delete("/b/2/dir/old", 3)).unwrap();
+
+	let mut html = Vec::new();
+	write_html(&dir_map, "/b/2/", &mut html).unwrap();
+	let html = String::from_utf8(html).unwrap();
+	assert_eq!(html.matches("</script>").count(), 2);
+
+	let start = html.find(r#"type="application/json">"#).unwrap() + 24;
+	let end = start + html[start..].find("</script>").unwrap();
+	let data: serde_json::Value = serde_json::from_str(&html[start..end]).unwrap();
+	assert_eq!(data["title"], "Changes in backup 2");
+	assert_eq!(data["tree"]["size"], 12);
+	assert_eq!(data["tree"]["children"][0]["name"], "dir");
+	assert_eq!(data["tree"]["children"][0]["removed_size"], 3);
+	assert_eq!(data["top"][0]["path"], "/b/2/dir/</script>");
+	assert_eq!(data["top"][2]["kind"], "Removed");
+}
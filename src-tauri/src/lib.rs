@@ -0,0 +1,40 @@
+use std::time::Instant;
+
+pub mod backend;
+pub mod cache;
+pub mod compare;
+pub mod destinationinfo;
+pub mod dir_map;
+pub mod error;
+pub mod exclusions;
+pub mod export;
+pub mod history;
+pub mod html_report;
+pub mod listbackups;
+pub mod restore;
+pub mod search;
+pub mod snapshot;
+#[cfg(test)]
+mod test_util;
+pub mod top;
+
+pub fn reset_dur(since: &mut Instant) -> f32 {
+	let dur = Instant::now().duration_since(*since).as_nanos() as f32;
+	*since = Instant::now();
+	dur / 1000.0 / 1000.0
+}
+
+/// Formats bytes using decimal units, like the UI
+pub fn format_size(size: u64) -> String {
+	let units = ["KB", "MB", "GB", "TB", "PB"];
+	if size < 1000 {
+		return format!("{} B", size);
+	}
+	let mut value = size as f64 / 1000.0;
+	let mut unit = 0;
+	while value >= 1000.0 && unit < units.len() - 1 {
+		value /= 1000.0;
+		unit += 1;
+	}
+	format!("{:.1} {}", value, units[unit])
+}
@@ -1,10 +1,10 @@
-use crate::cmd::{check_cmd_success, parse_output};
+use crate::backend::TmutilBackend;
+use crate::error::{Error, ErrorKind};
 use regex::Regex;
 use serde::Serialize;
 use specta::Type;
 use std::collections::HashMap;
 use std::path::PathBuf;
-use std::process::Command;
 
 #[derive(Serialize, Clone, Type, Default)]
 pub struct Destinations {
@@ -12,71 +12,82 @@ pub struct Destinations {
 	pub destinations: Option<HashMap<String, Destination>>,
 }
 impl Destinations {
-	pub fn get_destination<'a>(&'a mut self, id: &str) -> Result<&'a mut Destination, String> {
-		let paths = self
-			.destinations
-			.as_mut()
-			.ok_or("Destinations not loaded")?;
-		let destination = paths
-			.get_mut(id)
-			.ok_or(format!("Destination not found: {}", id));
+	pub fn get_destination<'a>(&'a mut self, id: &str) -> Result<&'a mut Destination, Error> {
+		let paths = self.destinations.as_mut().ok_or_else(|| {
+			Error::new(ErrorKind::DestinationsNotLoaded, "Destinations not loaded")
+		})?;
+		let destination = paths.get_mut(id).ok_or_else(|| {
+			Error::new(
+				ErrorKind::DestinationNotFound { id: id.to_string() },
+				format!("Destination not found: {}", id),
+			)
+		});
 		destination
 	}
 }
 
-#[derive(Serialize, Clone, Type)]
+#[derive(Serialize, Clone, Type, Debug)]
 pub struct Destination {
 	/// Backups, if loaded
 	pub backups: Option<Vec<Backup>>,
 	pub mount_point: String,
 }
 impl Destination {
-	pub fn load_backups_list<'a>(&'a mut self) -> Result<&Vec<Backup>, String> {
-		let backups = listbackups(&self.mount_point)?;
+	pub fn load_backups_list<'a>(
+		&'a mut self,
+		backend: &dyn TmutilBackend,
+	) -> Result<&Vec<Backup>, Error> {
+		let backups = listbackups(backend, &self.mount_point)?;
 		self.backups = Some(backups);
 		Ok(self.backups.as_ref().unwrap())
 	}
+	pub fn backups(&self) -> Result<&Vec<Backup>, Error> {
+		self.backups
+			.as_ref()
+			.ok_or_else(|| Error::new(ErrorKind::BackupListNotLoaded, "Backup list not loaded"))
+	}
+	pub fn backup_position(&self, path: &str) -> Result<usize, Error> {
+		match self.backups()?.iter().position(|b| b.path == path) {
+			Some(pos) => Ok(pos),
+			None => Err(Error::new(
+				ErrorKind::BackupNotFound {
+					path: path.to_string(),
+				},
+				format!("Unable to find backup {}", path),
+			)),
+		}
+	}
 }
 
 #[derive(Serialize, Clone, Type, Debug)]
 pub struct Backup {
 	pub path: String,
 	pub name: String,
+	/// Seconds from 1970-01-01 00:00:00 to the date and time in the backup's
+	/// name. Time Machine uses local time, so this is in the time zone the
+	/// backup was made in. `None` if the name isn't a date.
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub timestamp: Option<u64>,
+	/// Seconds since the previous backup
+	#[specta(type = Option<u32>)] // tauri bigint fix
+	pub interval: Option<u64>,
 }
 
-fn listbackups(mount_point: &str) -> Result<Vec<Backup>, String> {
-	println!("tmutil listbackups");
-
-	let output = Command::new("tmutil")
-		.arg("listbackups")
-		.arg("-d")
-		.arg(mount_point)
-		.output()
-		.expect("Error calling command");
-	check_cmd_success(&output.status, output.stderr.clone())?;
-	println!("Success listing backups");
-
-	let output_str = parse_output(output.stdout)?;
-	println!("{output_str}");
+fn listbackups(backend: &dyn TmutilBackend, mount_point: &str) -> Result<Vec<Backup>, Error> {
+	let output_str = backend.listbackups(mount_point)?;
 
 	let mut paths: Vec<_> = output_str
 		.trim()
 		.split('\n')
+		.filter(|s| !s.trim().is_empty())
 		.map(|s| s.to_string())
 		.collect();
 
 	let mut stored_machine_dir = None;
-	let mut get_machine_dir = || -> Result<String, String> {
+	let mut get_machine_dir = || -> Result<String, Error> {
 		match &stored_machine_dir {
 			None => {
-				println!("tmutil machinedirectory");
-				let output = Command::new("tmutil")
-					.arg("machinedirectory")
-					.output()
-					.expect("Error calling command");
-				check_cmd_success(&output.status, output.stderr.clone())?;
-				println!("Success getting machinedirectory");
-				let output_str = parse_output(output.stdout)?.trim().to_string();
+				let output_str = backend.machinedirectory()?.trim().to_string();
 				stored_machine_dir = Some(output_str.clone());
 				Ok(output_str)
 			}
@@ -95,23 +106,85 @@ fn listbackups(mount_point: &str) -> Result<Vec<Backup>, String> {
 		if path0.starts_with("/") {}
 	}
 
-	let backups = paths
+	let mut backups: Vec<_> = paths
 		.into_iter()
-		.map(|path| Backup {
-			name: name_from_path(&path),
-			path,
+		.map(|path| {
+			let name = name_from_path(&path);
+			Backup {
+				timestamp: parse_timestamp(&name),
+				interval: None,
+				name,
+				path,
+			}
 		})
 		.collect();
+	// oldest first. Backups without a date are kept at the start
+	backups.sort_by_key(|b| b.timestamp);
+	for i in 1..backups.len() {
+		if let (Some(prev), Some(current)) = (backups[i - 1].timestamp, backups[i].timestamp) {
+			backups[i].interval = Some(current - prev);
+		}
+	}
 	Ok(backups)
 }
 
+/// Parses a `YYYY-MM-DD-HHMMSS` backup name into seconds since 1970-01-01
+fn parse_timestamp(name: &str) -> Option<u64> {
+	let bytes = name.as_bytes();
+	if bytes.len() != 17 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'-' {
+		return None;
+	}
+	let number = |range: std::ops::Range<usize>| -> Option<u64> {
+		let digits = &name[range];
+		if !digits.bytes().all(|b| b.is_ascii_digit()) {
+			return None;
+		}
+		digits.parse().ok()
+	};
+	let year = number(0..4)?;
+	let month = number(5..7)?;
+	let day = number(8..10)?;
+	let hour = number(11..13)?;
+	let minute = number(13..15)?;
+	let second = number(15..17)?;
+	if year < 1970 || !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+		return None;
+	}
+	if hour > 23 || minute > 59 || second > 59 {
+		return None;
+	}
+
+	let days_before_year: u64 = (1970..year)
+		.map(|y| if is_leap_year(y) { 366 } else { 365 })
+		.sum();
+	let days_before_month: u64 = (1..month).map(|m| days_in_month(year, m)).sum();
+	let days = days_before_year + days_before_month + day - 1;
+	Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+fn is_leap_year(year: u64) -> bool {
+	year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+	match month {
+		2 if is_leap_year(year) => 29,
+		2 => 28,
+		4 | 6 | 9 | 11 => 30,
+		_ => 31,
+	}
+}
+
 fn name_from_path(path: &str) -> String {
 	let parts: Vec<_> = PathBuf::from(path)
 		.components()
 		.filter_map(|component| match component {
 			std::path::Component::Normal(name) => Some(name.to_string_lossy().to_string()),
 			std::path::Component::RootDir => Some("/".to_string()),
-			_ => panic!("Unexpected path component"),
+			std::path::Component::CurDir => Some(".".to_string()),
+			std::path::Component::ParentDir => Some("..".to_string()),
+			// only exists on Windows
+			std::path::Component::Prefix(_) => None,
 		})
 		.collect();
 
@@ -156,4 +229,20 @@ fn test_name_from_path() {
 		);
 	assert_eq!(name_from_path("/Volumes/Something/else"), "else");
 	assert_eq!(name_from_path("/Volumes"), "/Volumes");
+	// malformed paths
+	assert_eq!(name_from_path(""), "");
+	assert_eq!(name_from_path("../2022-08-09-032130"), "2022-08-09-032130");
+	assert_eq!(name_from_path("/Volumes/x/../y"), "../y");
+}
+
+#[test]
+fn test_parse_timestamp() {
+	assert_eq!(parse_timestamp("2022-08-09-032130"), Some(1660015290));
+	assert_eq!(parse_timestamp("2000-02-29-235959"), Some(951868799));
+	assert_eq!(parse_timestamp("1970-01-01-000000"), Some(0));
+	assert_eq!(parse_timestamp("2001-02-29-000000"), None);
+	assert_eq!(parse_timestamp("2022-08-09-246000"), None);
+	assert_eq!(parse_timestamp("2022-08-09-03213a"), None);
+	assert_eq!(parse_timestamp("2022-08-09-+32130"), None);
+	assert_eq!(parse_timestamp("Backups.backupdb"), None);
 }
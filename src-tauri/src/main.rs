@@ -4,18 +4,20 @@
 )]
 
 use std::thread;
-use std::time::Instant;
 use tauri::api::{dialog, shell};
 use tauri::{
-	command, AboutMetadata, CustomMenuItem, Manager, Menu, MenuEntry, MenuItem, Submenu, Window,
-	WindowBuilder, WindowUrl,
+	command, AboutMetadata, CustomMenuItem, Manager, Menu, MenuEntry, MenuItem, RunEvent, Submenu,
+	Window, WindowBuilder, WindowUrl,
 };
+use time_machine_inspector::{backend, cache};
 
+mod analysis;
 mod cmd;
-mod compare;
-mod destinationinfo;
-mod dir_map;
-mod listbackups;
+mod exclude;
+mod query;
+mod report;
+mod restoring;
+mod sizes;
 
 #[command]
 #[specta::specta]
@@ -26,19 +28,6 @@ fn error_popup(msg: String, win: Window) {
 	});
 }
 
-#[macro_export]
-macro_rules! throw {
-	($($arg:tt)*) => {{
-		return Err(format!($($arg)*))
-	}};
-}
-
-pub fn reset_dur(since: &mut Instant) -> f32 {
-	let dur = Instant::now().duration_since(*since).as_nanos() as f32;
-	*since = Instant::now();
-	dur / 1000.0 / 1000.0
-}
-
 fn main() {
 	#[cfg(debug_assertions)]
 	{
@@ -47,8 +36,28 @@ fn main() {
 				error_popup,
 				cmd::load_backup_list,
 				cmd::get_backup,
+				cmd::compare_backups,
+				cmd::cancel_backup,
+				cmd::close_destination,
 				cmd::backups_info,
-				destinationinfo::destinationinfo,
+				cmd::destinationinfo,
+				cmd::clear_cache,
+				sizes::backup_sizes,
+				sizes::cancel_size_estimates,
+				analysis::growth_timeline,
+				analysis::path_history,
+				analysis::churn_report,
+				analysis::path_versions,
+				analysis::cancel_analysis,
+				exclude::exclusion_suggestions,
+				exclude::add_exclusion,
+				exclude::comparison_exclusions,
+				report::export_comparison,
+				query::top_items,
+				query::search_comparison,
+				restoring::restore_plan,
+				restoring::restore_item,
+				restoring::cancel_restore,
 			],
 			"../bindings.ts",
 		)
@@ -59,16 +68,45 @@ fn main() {
 	let ctx = tauri::generate_context!();
 
 	tauri::Builder::default()
+		.manage(backend::from_env())
 		.manage(cmd::DestinationsState(Default::default()))
 		.manage(cmd::LoadedBackups(Default::default()))
+		.manage(sizes::SizeEstimates::default())
+		.manage(analysis::RunningAnalysis::default())
+		.manage(restoring::RunningRestore::default())
 		.invoke_handler(tauri::generate_handler![
 			error_popup,
 			cmd::load_backup_list,
 			cmd::get_backup,
+			cmd::compare_backups,
+			cmd::cancel_backup,
+			cmd::close_destination,
 			cmd::backups_info,
-			destinationinfo::destinationinfo,
+			cmd::destinationinfo,
+			cmd::clear_cache,
+			sizes::backup_sizes,
+			sizes::cancel_size_estimates,
+			analysis::growth_timeline,
+			analysis::path_history,
+			analysis::churn_report,
+			analysis::path_versions,
+			analysis::cancel_analysis,
+			exclude::exclusion_suggestions,
+			exclude::add_exclusion,
+			exclude::comparison_exclusions,
+			report::export_comparison,
+			query::top_items,
+			query::search_comparison,
+			restoring::restore_plan,
+			restoring::restore_item,
+			restoring::cancel_restore,
 		])
 		.setup(|app| {
+			let cache_dir = app.path_resolver().app_cache_dir();
+			let cache = cache_dir
+				.map(|dir| cache::DiskCache::new(dir.join("comparisons"), cache::DEFAULT_MAX_SIZE));
+			app.manage(cmd::CacheState(cache));
+
 			let _window = WindowBuilder::new(app, "main", WindowUrl::default())
 				.title("Time Machine Inspector")
 				.resizable(true)
@@ -78,8 +116,7 @@ fn main() {
 				.min_inner_size(600.0, 250.0)
 				.skip_taskbar(false)
 				.fullscreen(false)
-				.build()
-				.expect("Unable to create window");
+				.build()?;
 			Ok(())
 		})
 		.menu(Menu::with_items([
@@ -136,11 +173,33 @@ fn main() {
 				"Learn More" => {
 					let link =
 						"https://github.com/probablykasper/time-machine-inspector".to_string();
-					shell::open(&event.window().shell_scope(), link, None).unwrap();
+					if let Err(e) = shell::open(&event.window().shell_scope(), link, None) {
+						eprintln!("Unable to open link: {}", e);
+					}
 				}
 				_ => {}
 			}
 		})
-		.run(ctx)
-		.expect("error while running tauri application");
+		.build(ctx)
+		.expect("error while building tauri application")
+		.run(|app, event| match event {
+			RunEvent::Exit => {
+				// kill any tmutil processes that are still running
+				let loaded_backups = app.state::<cmd::LoadedBackups>();
+				if let Err(e) = loaded_backups.cancel_where(|_| true) {
+					eprintln!("{}", e);
+				}
+				let size_estimates = app.state::<sizes::SizeEstimates>();
+				if let Err(e) = size_estimates.cancel_where(|_| true) {
+					eprintln!("{}", e);
+				}
+				if let Err(e) = app.state::<analysis::RunningAnalysis>().cancel() {
+					eprintln!("{}", e);
+				}
+				if let Err(e) = app.state::<restoring::RunningRestore>().cancel() {
+					eprintln!("{}", e);
+				}
+			}
+			_ => {}
+		});
 }
@@ -0,0 +1,44 @@
+use crate::cmd;
+use tauri::{command, Window};
+use time_machine_inspector::error::Error;
+use time_machine_inspector::search::{self, SearchQuery, SearchResults};
+use time_machine_inspector::top::{self, TopFilter, TopItems};
+
+/// The `limit` largest changed files and directories in the comparison of
+/// `old_b` and `new_b`. The comparison is loaded like in `get_backup`.
+#[command]
+#[specta::specta]
+pub async fn top_items(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	filter: TopFilter,
+	limit: u32,
+	w: Window,
+) -> Result<TopItems, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	cmd::with_loaded_comparison(&w, &destination_id, &old_b, &new_b, |dir_map| {
+		top::top(dir_map, &new_b, &filter, limit as usize)
+	})
+	.await
+}
+
+/// Changed paths in the comparison of `old_b` and `new_b` that match `query`,
+/// with the directories above them. Fails while the comparison is loading,
+/// rather than finding no matches.
+#[command]
+#[specta::specta]
+pub async fn search_comparison(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	query: SearchQuery,
+	limit: u32,
+	w: Window,
+) -> Result<SearchResults, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	cmd::with_loaded_comparison(&w, &destination_id, &old_b, &new_b, |dir_map| {
+		search::search(dir_map, &new_b, &query, limit as usize)
+	})
+	.await?
+}
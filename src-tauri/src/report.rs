@@ -0,0 +1,53 @@
+use crate::cmd;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::{command, Window};
+use time_machine_inspector::error::Error;
+use time_machine_inspector::export::{self, ExportFormat};
+
+fn backup_name(backup_path: &str) -> &str {
+	Path::new(backup_path)
+		.file_name()
+		.and_then(|name| name.to_str())
+		.unwrap_or("comparison")
+}
+
+/// Exports the comparison of `old_b` and `new_b` to a file chosen in a save
+/// dialog. Returns the file path, or `None` if the dialog was cancelled.
+#[command]
+#[specta::specta]
+pub async fn export_comparison(
+	destination_id: String,
+	old_b: String,
+	new_b: String,
+	format: ExportFormat,
+	w: Window,
+) -> Result<Option<String>, Error> {
+	let file_name = format!("{}.{}", backup_name(&new_b), format.extension());
+	let path = FileDialogBuilder::new()
+		.set_parent(&w)
+		.set_title("Export Comparison")
+		.set_file_name(&file_name)
+		.add_filter(format!("{:?}", format), &[format.extension()])
+		.save_file();
+	let path = match path {
+		Some(path) => path,
+		None => return Ok(None),
+	};
+	// the file is only created once the comparison has loaded
+	cmd::with_loaded_comparison(&w, &destination_id, &old_b, &new_b, |dir_map| {
+		let file = match File::create(&path) {
+			Ok(file) => file,
+			Err(e) => return Err(Error::io("Unable to create", &path, e)),
+		};
+		let exported = export::export(dir_map, &new_b, format, BufWriter::new(file));
+		if exported.is_err() {
+			let _ = fs::remove_file(&path);
+		}
+		exported
+	})
+	.await??;
+	Ok(Some(path.to_string_lossy().to_string()))
+}
@@ -0,0 +1,346 @@
+use crate::backend::TmutilBackend;
+use crate::compare::CancelToken;
+use crate::error::{Error, ErrorKind};
+use serde::Serialize;
+use specta::Type;
+use std::fs::{self, File};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+/// Most entries listed in a `RestorePlan`, to keep large folders manageable
+pub const MAX_LISTED: usize = 1000;
+
+#[derive(Serialize, Type, Clone, Debug, PartialEq)]
+pub struct RestoreEntry {
+	/// Path relative to the parent of the restored item
+	pub path: String,
+	pub is_dir: bool,
+	#[specta(type = u32)] // tauri bigint fix
+	pub size: u64,
+}
+
+/// What a restore would copy, for a dry run and for progress
+#[derive(Serialize, Type, Clone, Debug)]
+pub struct RestorePlan {
+	/// Full path in the backup
+	pub source: String,
+	/// Where the item is restored to
+	pub destination: String,
+	/// Whether something already exists at `destination`
+	pub exists: bool,
+	/// The first `MAX_LISTED` files and folders, parents first
+	pub entries: Vec<RestoreEntry>,
+	pub file_count: u32,
+	#[specta(type = u32)] // tauri bigint fix
+	pub total_size: u64,
+}
+
+#[derive(Serialize, Type, Clone, Debug)]
+pub struct RestoreProgress {
+	/// Files and folders restored so far
+	pub done: u32,
+	/// Files and folders in the plan
+	pub total: u32,
+	pub current_path: String,
+}
+
+fn metadata(path: &Path) -> Result<fs::Metadata, Error> {
+	match fs::symlink_metadata(path) {
+		Ok(meta) => Ok(meta),
+		Err(e) => Err(Error::io("Unable to read", path, e)),
+	}
+}
+
+fn read_dir_sorted(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(e) => return Err(Error::io("Unable to read directory", dir, e)),
+	};
+	let mut paths = Vec::new();
+	for entry in entries {
+		match entry {
+			Ok(entry) => paths.push(entry.path()),
+			Err(e) => return Err(Error::io("Unable to read directory", dir, e)),
+		}
+	}
+	paths.sort();
+	Ok(paths)
+}
+
+impl RestorePlan {
+	/// Lists what restoring `source` into the folder `destination_dir` would
+	/// copy, without copying anything
+	pub fn new(source: &str, destination_dir: &str) -> Result<Self, Error> {
+		let source_path = Path::new(source.trim_end_matches('/'));
+		let name = match source_path.file_name() {
+			Some(name) => name,
+			None => return Err(Error::other(format!("Unable to restore {}", source))),
+		};
+		let destination = Path::new(destination_dir).join(name);
+		let mut plan = Self {
+			source: source_path.to_string_lossy().to_string(),
+			destination: destination.to_string_lossy().to_string(),
+			exists: fs::symlink_metadata(&destination).is_ok(),
+			entries: Vec::new(),
+			file_count: 0,
+			total_size: 0,
+		};
+		let parent = source_path.parent().unwrap_or(Path::new("/"));
+		plan.add(source_path, parent)?;
+		Ok(plan)
+	}
+	fn add(&mut self, path: &Path, parent: &Path) -> Result<(), Error> {
+		let meta = metadata(path)?;
+		let size = if meta.is_dir() { 0 } else { meta.len() };
+		self.file_count += 1;
+		self.total_size += size;
+		if self.entries.len() < MAX_LISTED {
+			let relative = path.strip_prefix(parent).unwrap_or(path);
+			self.entries.push(RestoreEntry {
+				path: relative.to_string_lossy().to_string(),
+				is_dir: meta.is_dir(),
+				size,
+			});
+		}
+		if meta.is_dir() {
+			for child in read_dir_sorted(path)? {
+				self.add(&child, parent)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Restores the plan's source through `backend`. If something exists at the
+/// destination, it's only replaced if `overwrite` is set, which should come
+/// from the user confirming it. The restore goes next to the destination and
+/// is only moved there once it's done, so a failed or cancelled restore
+/// leaves the destination as it was.
+pub fn restore(
+	backend: &dyn TmutilBackend,
+	plan: &RestorePlan,
+	overwrite: bool,
+	cancel: &CancelToken,
+	on_progress: &mut dyn FnMut(RestoreProgress),
+) -> Result<(), Error> {
+	let destination = Path::new(&plan.destination);
+	let exists = fs::symlink_metadata(destination).is_ok();
+	if exists && !overwrite {
+		let message = format!("{} already exists", plan.destination);
+		let path = plan.destination.clone();
+		return Err(Error::new(ErrorKind::AlreadyExists { path }, message));
+	}
+	let restore_to = sibling(destination, "restoring")?;
+	let restore_to_str = restore_to.to_string_lossy().to_string();
+	let mut done = 0;
+	let mut restored = backend.restore(&plan.source, &restore_to_str, cancel, &mut |path| {
+		done += 1;
+		// report where the path ends up, not the temporary path
+		let current_path = match path.strip_prefix(&restore_to_str) {
+			Some(rest) => format!("{}{}", plan.destination, rest),
+			None => path.to_string(),
+		};
+		on_progress(RestoreProgress {
+			done,
+			total: plan.file_count,
+			current_path,
+		});
+	});
+	if restored.is_ok() && cancel.is_cancelled() {
+		restored = Err(Error::cancelled());
+	}
+	if let Err(e) = restored {
+		let _ = remove(&restore_to);
+		return Err(e);
+	}
+	if exists {
+		replace(&restore_to, destination)
+	} else if let Err(e) = fs::rename(&restore_to, destination) {
+		let _ = remove(&restore_to);
+		Err(Error::io("Unable to restore to", destination, e))
+	} else {
+		Ok(())
+	}
+}
+
+/// A path next to `path` that doesn't exist yet
+fn sibling(path: &Path, suffix: &str) -> Result<PathBuf, Error> {
+	let name = path.file_name().unwrap_or_default().to_string_lossy();
+	for i in 0.. {
+		let candidate = path.with_file_name(format!(".{}.{}-{}", name, suffix, i));
+		if fs::symlink_metadata(&candidate).is_err() {
+			return Ok(candidate);
+		}
+	}
+	Err(Error::other(format!(
+		"Unable to restore next to {}",
+		path.display()
+	)))
+}
+
+fn remove(path: &Path) -> Result<(), Error> {
+	let removed = if metadata(path)?.is_dir() {
+		fs::remove_dir_all(path)
+	} else {
+		fs::remove_file(path)
+	};
+	match removed {
+		Ok(()) => Ok(()),
+		Err(e) => Err(Error::io("Unable to remove", path, e)),
+	}
+}
+
+/// Moves `restored` to `destination`, putting the original back if that fails
+fn replace(restored: &Path, destination: &Path) -> Result<(), Error> {
+	let original = sibling(destination, "original")?;
+	if let Err(e) = fs::rename(destination, &original) {
+		let _ = remove(restored);
+		return Err(Error::io("Unable to replace", destination, e));
+	}
+	if let Err(e) = fs::rename(restored, destination) {
+		let _ = fs::rename(&original, destination);
+		let _ = remove(restored);
+		return Err(Error::io("Unable to replace", destination, e));
+	}
+	remove(&original)
+}
+
+/// Copies `source` to `destination`, which must not exist, keeping
+/// modification times and permissions and recreating symlinks. Each path is
+/// passed to `on_path` once it's copied. For backups that are plain
+/// directories, where there's no `tmutil restore`.
+pub fn copy_tree(
+	source: &Path,
+	destination: &Path,
+	cancel: &CancelToken,
+	on_path: &mut dyn FnMut(&str),
+) -> Result<(), Error> {
+	if cancel.is_cancelled() {
+		return Err(Error::cancelled());
+	}
+	let meta = metadata(source)?;
+	if meta.is_dir() {
+		if let Err(e) = fs::create_dir(destination) {
+			return Err(Error::io("Unable to create", destination, e));
+		}
+		for child in read_dir_sorted(source)? {
+			let name = child.file_name().unwrap_or_default();
+			copy_tree(&child, &destination.join(name), cancel, on_path)?;
+		}
+		if let Err(e) = fs::set_permissions(destination, meta.permissions()) {
+			return Err(Error::io("Unable to set permissions of", destination, e));
+		}
+	} else if meta.file_type().is_symlink() {
+		let target = match fs::read_link(source) {
+			Ok(target) => target,
+			Err(e) => return Err(Error::io("Unable to read", source, e)),
+		};
+		if let Err(e) = symlink(target, destination) {
+			return Err(Error::io("Unable to create", destination, e));
+		}
+	} else {
+		if let Err(e) = fs::copy(source, destination) {
+			return Err(Error::io("Unable to copy", source, e));
+		}
+		let set_modified = meta.modified().and_then(|modified| {
+			File::options()
+				.write(true)
+				.open(destination)?
+				.set_modified(modified)
+		});
+		if let Err(e) = set_modified {
+			return Err(Error::io(
+				"Unable to set the modification time of",
+				destination,
+				e,
+			));
+		}
+	}
+	on_path(&destination.to_string_lossy());
+	Ok(())
+}
+
+#[test]
+fn test_restore() {
+	use crate::backend::SnapshotBackend;
+	use crate::test_util::{fixture_backend, temp_dir};
+
+	let root = temp_dir("restore");
+	let backup = root.join("backups/2024-01-01-000000");
+	let target = root.join("target");
+	fs::create_dir_all(backup.join("docs/sub")).unwrap();
+	fs::create_dir_all(&target).unwrap();
+	fs::write(backup.join("docs/a.txt"), "hello").unwrap();
+	fs::write(backup.join("docs/sub/b.txt"), "hi").unwrap();
+	symlink("a.txt", backup.join("docs/link")).unwrap();
+
+	let source = backup.join("docs").to_string_lossy().to_string();
+	let plan = RestorePlan::new(&source, &target.to_string_lossy()).unwrap();
+	let listed: Vec<_> = plan.entries.iter().map(|e| e.path.as_str()).collect();
+	assert_eq!(
+		listed,
+		[
+			"docs",
+			"docs/a.txt",
+			"docs/link",
+			"docs/sub",
+			"docs/sub/b.txt"
+		]
+	);
+	assert_eq!(plan.file_count, 5);
+	assert!(!plan.exists);
+	assert_eq!(plan.destination, target.join("docs").to_string_lossy());
+
+	let backend = SnapshotBackend::new(root.join("backups"));
+	let cancel = CancelToken::new();
+	// cancelled partway, so nothing is left at the new destination
+	let error = restore(&backend, &plan, false, &cancel, &mut |_| cancel.cancel()).unwrap_err();
+	assert_eq!(error.kind, ErrorKind::Cancelled);
+	assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+
+	let cancel = CancelToken::new();
+	let mut progress = Vec::new();
+	restore(&backend, &plan, false, &cancel, &mut |p| {
+		progress.push((p.done, p.current_path))
+	})
+	.unwrap();
+	let done: Vec<_> = progress.iter().map(|(done, _)| *done).collect();
+	assert_eq!(done, [1, 2, 3, 4, 5]);
+	assert_eq!(progress[0].1, target.join("docs/a.txt").to_string_lossy());
+	let restored = target.join("docs");
+	assert_eq!(
+		fs::read_to_string(restored.join("sub/b.txt")).unwrap(),
+		"hi"
+	);
+	assert_eq!(fs::read_to_string(restored.join("link")).unwrap(), "hello");
+	let modified = |path: &Path| fs::metadata(path).unwrap().modified().unwrap();
+	assert_eq!(
+		modified(&restored.join("a.txt")),
+		modified(&backup.join("docs/a.txt"))
+	);
+
+	fs::write(restored.join("a.txt"), "changed").unwrap();
+	let plan = RestorePlan::new(&source, &target.to_string_lossy()).unwrap();
+	assert!(plan.exists);
+	let error = restore(&backend, &plan, false, &cancel, &mut |_| {}).unwrap_err();
+	assert!(matches!(error.kind, ErrorKind::AlreadyExists { .. }));
+	assert_eq!(
+		fs::read_to_string(restored.join("a.txt")).unwrap(),
+		"changed"
+	);
+
+	assert!(restore(&fixture_backend(), &plan, true, &cancel, &mut |_| {}).is_err());
+	cancel.cancel();
+	assert!(restore(&backend, &plan, true, &cancel, &mut |_| {}).is_err());
+	assert_eq!(
+		fs::read_to_string(restored.join("a.txt")).unwrap(),
+		"changed"
+	);
+	assert_eq!(fs::read_dir(&target).unwrap().count(), 1);
+
+	let cancel = CancelToken::new();
+	restore(&backend, &plan, true, &cancel, &mut |_| {}).unwrap();
+	assert_eq!(fs::read_to_string(restored.join("a.txt")).unwrap(), "hello");
+	assert_eq!(fs::read_dir(&target).unwrap().count(), 1);
+	fs::remove_dir_all(&root).unwrap();
+}
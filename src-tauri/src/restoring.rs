@@ -0,0 +1,89 @@
+use crate::cmd;
+use std::sync::{Mutex, MutexGuard};
+use tauri::api::dialog::blocking::FileDialogBuilder;
+use tauri::{command, State, Window};
+use time_machine_inspector::backend::BackendState;
+use time_machine_inspector::compare::CancelToken;
+use time_machine_inspector::error::Error;
+use time_machine_inspector::restore::{self, RestorePlan};
+
+pub const RESTORE_PROGRESS_EVENT: &str = "restore-progress";
+
+/// The restore that's running, if any. Starting one cancels the previous
+/// one.
+#[derive(Default)]
+pub struct RunningRestore(Mutex<Option<CancelToken>>);
+
+impl RunningRestore {
+	fn lock(&self) -> Result<MutexGuard<'_, Option<CancelToken>>, Error> {
+		match self.0.lock() {
+			Ok(mutex) => Ok(mutex),
+			Err(e) => Err(Error::other(format!("Unable to lock restore: {}", e))),
+		}
+	}
+	pub fn start(&self) -> Result<CancelToken, Error> {
+		let cancel = CancelToken::new();
+		if let Some(previous) = self.lock()?.replace(cancel.clone()) {
+			previous.cancel();
+		}
+		Ok(cancel)
+	}
+	pub fn cancel(&self) -> Result<(), Error> {
+		if let Some(cancel) = self.lock()?.take() {
+			cancel.cancel();
+		}
+		Ok(())
+	}
+}
+
+/// Asks for a folder to restore `source` into, and lists what would be
+/// copied there without copying anything. Returns `None` if the dialog was
+/// cancelled.
+#[command]
+#[specta::specta]
+pub async fn restore_plan(source: String, w: Window) -> Result<Option<RestorePlan>, Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	let folder = FileDialogBuilder::new()
+		.set_parent(&w)
+		.set_title("Restore To")
+		.pick_folder();
+	match folder {
+		Some(folder) => Ok(Some(RestorePlan::new(&source, &folder.to_string_lossy())?)),
+		None => Ok(None),
+	}
+}
+
+/// Restores `source` into the folder `destination_dir`, emitting
+/// `RESTORE_PROGRESS_EVENT` for each restored path. Set `overwrite` to
+/// replace what's already there, once the user has confirmed it.
+#[command]
+#[specta::specta]
+pub async fn restore_item(
+	source: String,
+	destination_dir: String,
+	overwrite: bool,
+	w: Window,
+	backend: State<'_, BackendState>,
+	running: State<'_, RunningRestore>,
+) -> Result<(), Error> {
+	cmd::full_disk_access(w.clone()).await?;
+	let plan = RestorePlan::new(&source, &destination_dir)?;
+	let cancel = running.start()?;
+	restore::restore(
+		backend.0.as_ref(),
+		&plan,
+		overwrite,
+		&cancel,
+		&mut |progress| {
+			if let Err(e) = w.emit(RESTORE_PROGRESS_EVENT, progress) {
+				eprintln!("Unable to emit restore progress: {}", e);
+			}
+		},
+	)
+}
+
+#[command]
+#[specta::specta]
+pub async fn cancel_restore(running: State<'_, RunningRestore>) -> Result<(), Error> {
+	running.cancel()
+}
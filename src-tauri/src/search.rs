@@ -0,0 +1,193 @@
+use crate::dir_map::{is_at_or_below, join_path, ChangeKind, DirMap, LoadedBackupItem};
+use crate::error::Error;
+use crate::exclusions::glob_to_regex;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Type, Debug, PartialEq)]
+pub enum SearchMode {
+	Substring,
+	/// A glob over the full path, like `**/*.mp4`
+	Glob,
+	Regex,
+}
+
+#[derive(Deserialize, Type, Debug, Clone)]
+pub struct SearchQuery {
+	pub pattern: String,
+	pub mode: SearchMode,
+	pub case_sensitive: bool,
+	/// Only match changed files of these kinds. Empty matches everything,
+	/// including directories.
+	pub kinds: Vec<ChangeKind>,
+	/// Minimum size in bytes, see `match_size`
+	#[specta(type = u32)] // tauri bigint fix
+	pub min_size: u64,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct SearchMatch {
+	pub path: String,
+	pub is_dir: bool,
+	pub item: LoadedBackupItem,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct SearchResults {
+	/// Matches sorted by path, at most the requested limit
+	pub matches: Vec<SearchMatch>,
+	/// Directories between the backup and the matches, for expanding the tree
+	pub ancestors: Vec<String>,
+	/// Whether matches were left out because of the limit
+	pub truncated: bool,
+}
+
+impl SearchQuery {
+	fn regex(&self) -> Result<Regex, Error> {
+		let pattern = match self.mode {
+			SearchMode::Substring => regex::escape(&self.pattern),
+			SearchMode::Glob => glob_to_regex(&self.pattern),
+			SearchMode::Regex => self.pattern.clone(),
+		};
+		match RegexBuilder::new(&pattern)
+			.case_insensitive(!self.case_sensitive)
+			.build()
+		{
+			Ok(regex) => Ok(regex),
+			Err(e) => {
+				let message = format!("Invalid search pattern {}: {}", self.pattern, e);
+				Err(Error::parse_value(message, &self.pattern))
+			}
+		}
+	}
+}
+
+/// Size compared against `min_size`: the bytes removed for removed files, and
+/// the bytes added or updated for everything else
+fn match_size(item: &LoadedBackupItem) -> u64 {
+	match &item.change {
+		Some(change) if change.kind == ChangeKind::Removed => item.removed_size,
+		_ => item.size,
+	}
+}
+
+/// Finds changed files and directories below `backup_path` whose full path
+/// matches `query`
+pub fn search(
+	dir_map: &DirMap,
+	backup_path: &str,
+	query: &SearchQuery,
+	limit: usize,
+) -> Result<SearchResults, Error> {
+	let backup_path = backup_path.trim_end_matches('/');
+	let regex = query.regex()?;
+	let mut matches = Vec::new();
+	for (dir, contents) in &dir_map.map {
+		if !is_at_or_below(dir, backup_path) {
+			continue;
+		}
+		for (name, item) in contents {
+			let kind_matches = match &item.change {
+				_ if query.kinds.is_empty() => true,
+				Some(change) => query.kinds.contains(&change.kind),
+				None => false,
+			};
+			if !kind_matches || match_size(item) < query.min_size {
+				continue;
+			}
+			let path = join_path(dir, name);
+			if regex.is_match(&path) {
+				matches.push((path, item));
+			}
+		}
+	}
+	matches.sort_by(|(a, _), (b, _)| a.cmp(b));
+	let truncated = matches.len() > limit;
+	matches.truncate(limit);
+
+	let mut ancestors = BTreeSet::new();
+	for (path, _) in &matches {
+		for ancestor in Path::new(path).ancestors().skip(1) {
+			let ancestor = ancestor.to_string_lossy();
+			if ancestor.len() <= backup_path.len() || !ancestors.insert(ancestor.to_string()) {
+				break;
+			}
+		}
+	}
+	let matches = matches
+		.into_iter()
+		.map(|(path, item)| SearchMatch {
+			is_dir: dir_map.map.contains_key(&path),
+			path,
+			item: item.clone(),
+		})
+		.collect();
+	Ok(SearchResults {
+		matches,
+		ancestors: ancestors.into_iter().collect(),
+		truncated,
+	})
+}
+
+#[test]
+fn test_search() {
+	use crate::test_util::{add, delete};
+
+	let mut dir_map = DirMap::new();
+	dir_map.add_change(add("/b/2/Movies/a.mp4", 50)).unwrap();
+	dir_map.add_change(add("/b/2/Movies/b.MP4", 5)).unwrap();
+	dir_map.add_change(add("/b/2/Docs/notes.txt", 10)).unwrap();
+	dir_map
+		.add_change(delete("/b/2/Docs/old/clip.mp4", 30))
+		.unwrap();
+
+	let mut query = SearchQuery {
+		pattern: "mp4".to_string(),
+		mode: SearchMode::Substring,
+		case_sensitive: false,
+		kinds: Vec::new(),
+		min_size: 0,
+	};
+	let paths = |results: SearchResults| -> Vec<String> {
+		results.matches.into_iter().map(|m| m.path).collect()
+	};
+	let results = search(&dir_map, "/b/2", &query, 10).unwrap();
+	assert_eq!(
+		results.ancestors,
+		["/b/2/Docs", "/b/2/Docs/old", "/b/2/Movies"]
+	);
+	assert_eq!(
+		paths(results),
+		[
+			"/b/2/Docs/old/clip.mp4",
+			"/b/2/Movies/a.mp4",
+			"/b/2/Movies/b.MP4"
+		]
+	);
+
+	query.min_size = 20;
+	query.kinds = vec![ChangeKind::Added];
+	let results = search(&dir_map, "/b/2", &query, 10).unwrap();
+	assert_eq!(paths(results), ["/b/2/Movies/a.mp4"]);
+
+	query.mode = SearchMode::Glob;
+	query.pattern = "**/Docs".to_string();
+	query.kinds = Vec::new();
+	query.min_size = 0;
+	let results = search(&dir_map, "/b/2", &query, 10).unwrap();
+	assert!(results.matches[0].is_dir);
+	assert_eq!(paths(results), ["/b/2/Docs"]);
+
+	query.mode = SearchMode::Regex;
+	query.pattern = r"\.mp4$".to_string();
+	query.case_sensitive = true;
+	let results = search(&dir_map, "/b/2", &query, 1).unwrap();
+	assert!(results.truncated);
+	assert_eq!(paths(results), ["/b/2/Docs/old/clip.mp4"]);
+
+	query.pattern = "(".to_string();
+	assert!(search(&dir_map, "/b/2", &query, 10).is_err());
+}
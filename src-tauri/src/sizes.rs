@@ -0,0 +1,171 @@
+use crate::cmd::{self, CacheState, LoadedBackups};
+use serde::Serialize;
+use specta::Type;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use tauri::{command, AppHandle, Manager, State};
+use time_machine_inspector::compare::{CancelToken, Totals};
+use time_machine_inspector::error::{Error, ErrorKind};
+use time_machine_inspector::listbackups::Backup;
+
+pub const BACKUP_SIZE_EVENT: &str = "backup-size";
+
+/// Totals of a backup compared to the one before it
+#[derive(Serialize, Clone, Type)]
+pub struct BackupSize {
+	pub destination_id: String,
+	pub old: String,
+	pub new: String,
+	pub totals: Totals,
+}
+
+struct DestinationSizes {
+	cancel: CancelToken,
+	sizes: Vec<BackupSize>,
+}
+
+/// Size estimates by destination ID
+#[derive(Default)]
+pub struct SizeEstimates(Mutex<HashMap<String, DestinationSizes>>);
+
+impl SizeEstimates {
+	fn lock(&self) -> Result<MutexGuard<HashMap<String, DestinationSizes>>, Error> {
+		match self.0.lock() {
+			Ok(mutex) => Ok(mutex),
+			Err(e) => Err(Error::other(format!(
+				"Unable to lock size estimates: {}",
+				e
+			))),
+		}
+	}
+	/// Starts estimating the sizes of the backups in a background thread,
+	/// unless that's already been done
+	pub fn start(
+		&self,
+		destination_id: &str,
+		backups: Vec<Backup>,
+		refresh: bool,
+		app: AppHandle,
+	) -> Result<(), Error> {
+		let cancel = CancelToken::new();
+		{
+			let mut estimates = self.lock()?;
+			if let Some(existing) = estimates.get(destination_id) {
+				if !refresh && !existing.cancel.is_cancelled() {
+					return Ok(());
+				}
+				existing.cancel.cancel();
+			}
+			let sizes = DestinationSizes {
+				cancel: cancel.clone(),
+				sizes: Vec::new(),
+			};
+			estimates.insert(destination_id.to_string(), sizes);
+		}
+		let destination_id = destination_id.to_string();
+		thread::spawn(move || estimate_sizes(&app, &destination_id, &backups, &cancel));
+		Ok(())
+	}
+	/// Sizes of the destination that have been estimated so far
+	pub fn sizes(&self, destination_id: &str) -> Result<Vec<BackupSize>, Error> {
+		match self.lock()?.get(destination_id) {
+			Some(destination) => Ok(destination.sizes.clone()),
+			None => Ok(Vec::new()),
+		}
+	}
+	/// Cancels the estimates of the destinations matching `filter`
+	pub fn cancel_where(&self, filter: impl Fn(&str) -> bool) -> Result<(), Error> {
+		for (destination_id, estimates) in self.lock()?.iter() {
+			if filter(destination_id) {
+				estimates.cancel.cancel();
+			}
+		}
+		Ok(())
+	}
+}
+
+/// Estimates the sizes of the backups, newest first. Comparisons that are
+/// already loaded or cached are reused, and new ones are cached.
+fn estimate_sizes(app: &AppHandle, destination_id: &str, backups: &[Backup], cancel: &CancelToken) {
+	for pair in backups.windows(2).rev() {
+		let (old, new) = (&pair[0].path, &pair[1].path);
+		let totals = match backup_totals(app, destination_id, old, new, cancel) {
+			Ok(totals) => totals,
+			Err(e) if e.kind == ErrorKind::Cancelled => return,
+			Err(e) => {
+				eprintln!("Unable to estimate size of {}: {}", new, e);
+				continue;
+			}
+		};
+		let size = BackupSize {
+			destination_id: destination_id.to_string(),
+			old: old.clone(),
+			new: new.clone(),
+			totals,
+		};
+		{
+			let state = app.state::<SizeEstimates>();
+			let mut estimates = match state.lock() {
+				Ok(estimates) => estimates,
+				Err(_) => return,
+			};
+			// a cancelled estimate may have been replaced by a new one
+			if cancel.is_cancelled() {
+				return;
+			}
+			if let Some(destination) = estimates.get_mut(destination_id) {
+				destination.sizes.push(size.clone());
+			}
+		}
+		if let Err(e) = app.emit_all(BACKUP_SIZE_EVENT, size) {
+			eprintln!("Unable to emit backup size: {}", e);
+		}
+	}
+}
+
+fn backup_totals(
+	app: &AppHandle,
+	destination_id: &str,
+	old: &str,
+	new: &str,
+	cancel: &CancelToken,
+) -> Result<Totals, Error> {
+	let old_new = (old.to_string(), new.to_string());
+	if let Some(loaded_backup) = app.state::<LoadedBackups>().lock()?.get(&old_new) {
+		if !loaded_backup.loading {
+			return Ok(loaded_backup.map.totals.clone());
+		}
+	}
+	// only the totals are read, not the whole comparison
+	let cache = app.state::<CacheState>();
+	if let Some(cache) = &cache.0 {
+		if let Some(totals) = cache.load_totals(destination_id, old, new) {
+			return Ok(totals);
+		}
+	}
+	cmd::with_comparison(app, destination_id, old, new, cancel, |dir_map| {
+		dir_map.totals.clone()
+	})
+}
+
+/// Sizes that have been estimated so far
+#[command]
+#[specta::specta]
+pub async fn backup_sizes(
+	destination_id: String,
+	size_estimates: State<'_, SizeEstimates>,
+) -> Result<Vec<BackupSize>, Error> {
+	size_estimates.sizes(&destination_id)
+}
+
+/// Stops estimating the sizes of the destination's backups, without
+/// cancelling its comparisons
+#[command]
+#[specta::specta]
+pub async fn cancel_size_estimates(
+	destination_id: String,
+	size_estimates: State<'_, SizeEstimates>,
+) -> Result<(), Error> {
+	size_estimates.cancel_where(|id| id == destination_id)
+}
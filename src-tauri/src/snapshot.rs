@@ -0,0 +1,296 @@
+use crate::compare::{Add, CancelToken, Change, Delete, Item, OnChange, Totals, Update};
+use crate::error::{Error, ErrorKind};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Compares two backup snapshots on disk, producing the same result as
+/// `tmutil compare -X -s`. Snapshots are expected to be hard-link trees (HFS+
+/// Time Machine backups, rsnapshot, `rsync --link-dest`), so anything that is
+/// the same inode in both is skipped without being read. `cancel` is checked
+/// before each directory is read.
+pub fn compare_snapshots(
+	old: &Path,
+	new: &Path,
+	cancel: &CancelToken,
+	on_change: &mut OnChange,
+) -> Result<Totals, Error> {
+	let old_meta = metadata(old)?;
+	let new_meta = metadata(new)?;
+	for (path, meta) in [(old, &old_meta), (new, &new_meta)] {
+		if !meta.is_dir() {
+			let path = path.to_string_lossy().to_string();
+			let message = format!("Not a directory: {}", path);
+			return Err(Error::new(ErrorKind::Io { path }, message));
+		}
+	}
+
+	let mut diff = SnapshotDiff {
+		cancel,
+		on_change,
+		totals: Totals {
+			added_size: 0,
+			changed_size: 0,
+			removed_size: 0,
+		},
+	};
+	if !same_inode(&old_meta, &new_meta) {
+		diff.compare_dirs(old, new)?;
+	}
+	Ok(diff.totals)
+}
+
+/// How a single path changed between two backups on disk, without looking
+/// inside directories, so directories that exist in both are unchanged. `old`
+/// is `None` for the first backup, where an existing path counts as added.
+pub fn compare_path(old: Option<&Path>, new: &Path) -> Result<Option<Change>, Error> {
+	let old_meta = match old {
+		Some(old) => optional_metadata(old)?,
+		None => None,
+	};
+	let new_meta = optional_metadata(new)?;
+	let change = match (old_meta, new_meta) {
+		(None, None) => None,
+		(None, Some(new_meta)) => Some(Change::Add(Add {
+			added_item: item(new, &new_meta),
+		})),
+		(Some(old_meta), None) => Some(Change::Delete(Delete {
+			removed_item: item(old.unwrap_or(new), &old_meta),
+		})),
+		(Some(old_meta), Some(new_meta)) => {
+			let differences = differences(&old_meta, &new_meta);
+			let same_kind = old_meta.is_dir() == new_meta.is_dir();
+			if same_inode(&old_meta, &new_meta)
+				|| (old_meta.is_dir() && new_meta.is_dir())
+				|| (same_kind && differences.is_empty())
+			{
+				None
+			} else {
+				Some(Change::Update(Update {
+					differences,
+					newer_item: item(new, &new_meta),
+					older_item: item(old.unwrap_or(new), &old_meta),
+				}))
+			}
+		}
+	};
+	Ok(change)
+}
+
+struct SnapshotDiff<'a> {
+	cancel: &'a CancelToken,
+	on_change: &'a mut OnChange<'a>,
+	totals: Totals,
+}
+
+impl SnapshotDiff<'_> {
+	fn read_names(&self, dir: &Path) -> Result<BTreeSet<OsString>, Error> {
+		if self.cancel.is_cancelled() {
+			return Err(Error::cancelled());
+		}
+		read_names(dir)
+	}
+
+	fn compare_dirs(&mut self, old: &Path, new: &Path) -> Result<(), Error> {
+		let old_names = self.read_names(old)?;
+		let new_names = self.read_names(new)?;
+
+		for name in &new_names {
+			let new_path = new.join(name);
+			let new_meta = metadata(&new_path)?;
+			if !old_names.contains(name) {
+				self.add_tree(&new_path, &new_meta)?;
+				continue;
+			}
+			let old_path = old.join(name);
+			let old_meta = metadata(&old_path)?;
+			if same_inode(&old_meta, &new_meta) {
+				continue;
+			}
+			match (old_meta.is_dir(), new_meta.is_dir()) {
+				(true, true) => self.compare_dirs(&old_path, &new_path)?,
+				(false, false) => {
+					let differences = differences(&old_meta, &new_meta);
+					if !differences.is_empty() {
+						self.totals.changed_size += new_meta.len();
+						(self.on_change)(
+							Change::Update(Update {
+								differences,
+								newer_item: item(&new_path, &new_meta),
+								older_item: item(&old_path, &old_meta),
+							}),
+							0,
+						)?;
+					}
+				}
+				_ => {
+					self.delete_tree(&old_path, &old_meta)?;
+					self.add_tree(&new_path, &new_meta)?;
+				}
+			}
+		}
+
+		for name in old_names.difference(&new_names) {
+			let old_path = old.join(name);
+			let old_meta = metadata(&old_path)?;
+			self.delete_tree(&old_path, &old_meta)?;
+		}
+		Ok(())
+	}
+
+	fn add_tree(&mut self, path: &Path, meta: &Metadata) -> Result<(), Error> {
+		if meta.is_dir() {
+			for name in self.read_names(path)? {
+				let child = path.join(name);
+				self.add_tree(&child, &metadata(&child)?)?;
+			}
+		} else {
+			self.totals.added_size += meta.len();
+			(self.on_change)(
+				Change::Add(Add {
+					added_item: item(path, meta),
+				}),
+				0,
+			)?;
+		}
+		Ok(())
+	}
+
+	fn delete_tree(&mut self, path: &Path, meta: &Metadata) -> Result<(), Error> {
+		if meta.is_dir() {
+			for name in self.read_names(path)? {
+				let child = path.join(name);
+				self.delete_tree(&child, &metadata(&child)?)?;
+			}
+		} else {
+			self.totals.removed_size += meta.len();
+			(self.on_change)(
+				Change::Delete(Delete {
+					removed_item: item(path, meta),
+				}),
+				0,
+			)?;
+		}
+		Ok(())
+	}
+}
+
+fn metadata(path: &Path) -> Result<Metadata, Error> {
+	match fs::symlink_metadata(path) {
+		Ok(meta) => Ok(meta),
+		Err(e) => Err(Error::io("Unable to read", path, e)),
+	}
+}
+
+fn optional_metadata(path: &Path) -> Result<Option<Metadata>, Error> {
+	match fs::symlink_metadata(path) {
+		Ok(meta) => Ok(Some(meta)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+		Err(e) => Err(Error::io("Unable to read", path, e)),
+	}
+}
+
+/// Names are kept as they are on disk, as they may not be valid UTF-8
+fn read_names(dir: &Path) -> Result<BTreeSet<OsString>, Error> {
+	let entries = match fs::read_dir(dir) {
+		Ok(entries) => entries,
+		Err(e) => return Err(Error::io("Unable to read directory", dir, e)),
+	};
+	let mut names = BTreeSet::new();
+	for entry in entries {
+		match entry {
+			Ok(entry) => names.insert(entry.file_name()),
+			Err(e) => return Err(Error::io("Unable to read directory", dir, e)),
+		};
+	}
+	Ok(names)
+}
+
+fn same_inode(a: &Metadata, b: &Metadata) -> bool {
+	a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Files that aren't hard links of each other are only considered changed if
+/// their size, modification time or ownership differ, like rsync's quick check
+fn differences(old: &Metadata, new: &Metadata) -> Vec<String> {
+	let mut differences = Vec::new();
+	if old.len() != new.len() {
+		differences.push("Size".to_string());
+	}
+	if old.mtime() != new.mtime() || old.mtime_nsec() != new.mtime_nsec() {
+		differences.push("ModificationDate".to_string());
+	}
+	if old.mode() != new.mode() {
+		differences.push("Permissions".to_string());
+	}
+	if old.uid() != new.uid() {
+		differences.push("Owner".to_string());
+	}
+	if old.gid() != new.gid() {
+		differences.push("Group".to_string());
+	}
+	differences
+}
+
+fn item(path: &Path, meta: &Metadata) -> Item {
+	Item {
+		path: path.to_string_lossy().to_string(),
+		size: if meta.is_dir() { 0 } else { meta.len() },
+	}
+}
+
+#[test]
+fn test_compare_snapshots() {
+	use std::os::unix::ffi::OsStrExt;
+
+	let root = crate::test_util::temp_dir("snapshot");
+	let old = root.join("2024-01-01-000000");
+	let new = root.join("2024-01-02-000000");
+	fs::create_dir_all(old.join("docs")).unwrap();
+	fs::create_dir_all(old.join("gone")).unwrap();
+	fs::create_dir_all(new.join("docs")).unwrap();
+	fs::create_dir_all(new.join("cache/sub")).unwrap();
+	fs::write(old.join("docs/same.txt"), "same").unwrap();
+	fs::hard_link(old.join("docs/same.txt"), new.join("docs/same.txt")).unwrap();
+	fs::write(old.join("docs/edited.txt"), "before").unwrap();
+	fs::write(new.join("docs/edited.txt"), "after!!!").unwrap();
+	fs::write(old.join("gone/a.bin"), [0; 100]).unwrap();
+	fs::write(new.join("cache/sub/b.bin"), [0; 40]).unwrap();
+	// not valid UTF-8, which is allowed on Linux
+	let invalid = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+	fs::write(new.join("docs").join(invalid), "new").unwrap();
+
+	let mut changes = Vec::new();
+	let cancel = CancelToken::new();
+	let totals = compare_snapshots(&old, &new, &cancel, &mut |change, _| {
+		changes.push(change);
+		Ok(())
+	})
+	.unwrap();
+	cancel.cancel();
+	let cancelled = compare_snapshots(&old, &new, &cancel, &mut |_, _| Ok(())).unwrap_err();
+	assert_eq!(cancelled.kind, ErrorKind::Cancelled);
+	fs::remove_dir_all(&root).unwrap();
+
+	assert_eq!(changes.len(), 4);
+	assert_eq!(totals.added_size, 43);
+	assert_eq!(totals.changed_size, 8);
+	assert_eq!(totals.removed_size, 100);
+	match &changes[0] {
+		Change::Add(add) => assert!(add.added_item.path.ends_with("cache/sub/b.bin")),
+		change => panic!("Expected add, got {:?}", change),
+	}
+	match &changes[1] {
+		Change::Add(add) => assert!(add.added_item.path.ends_with("docs/caf\u{fffd}.txt")),
+		change => panic!("Expected add, got {:?}", change),
+	}
+	match &changes[2] {
+		Change::Update(update) => {
+			assert!(update.differences.contains(&"Size".to_string()));
+			assert_eq!(update.older_item.size, 6);
+		}
+		change => panic!("Expected update, got {:?}", change),
+	}
+}
@@ -0,0 +1,48 @@
+//! Setup shared by the unit tests
+
+use crate::backend::FixtureBackend;
+use crate::compare::{Add, Change, Delete, Item};
+use crate::listbackups::{Backup, Destination};
+use std::fs;
+use std::path::PathBuf;
+
+/// Backend serving the recorded output in `fixtures`
+pub fn fixture_backend() -> FixtureBackend {
+	FixtureBackend::new(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"))
+}
+
+/// Backups listed by `backend`, for the destination the fixtures were
+/// recorded from
+pub fn fixture_backups(backend: &FixtureBackend) -> Vec<Backup> {
+	let mut destination = Destination {
+		backups: None,
+		mount_point: "/Volumes/Backups".to_string(),
+	};
+	destination.load_backups_list(backend).unwrap().clone()
+}
+
+pub fn add(path: &str, size: u64) -> Change {
+	Change::Add(Add {
+		added_item: Item {
+			path: path.to_string(),
+			size,
+		},
+	})
+}
+
+pub fn delete(path: &str, size: u64) -> Change {
+	Change::Delete(Delete {
+		removed_item: Item {
+			path: path.to_string(),
+			size,
+		},
+	})
+}
+
+/// Path for a test to create files in, unique to `name` and the process.
+/// Anything left there by an earlier run is removed.
+pub fn temp_dir(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("tmi-{}-{}", name, std::process::id()));
+	let _ = fs::remove_dir_all(&dir);
+	dir
+}
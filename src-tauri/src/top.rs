@@ -0,0 +1,179 @@
+use crate::dir_map::{is_at_or_below, join_path, ChangeKind, DirMap};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Filters for `top`
+#[derive(Deserialize, Type, Debug, Clone, Default)]
+pub struct TopFilter {
+	/// Only include paths at or below this full path
+	pub prefix: Option<String>,
+	/// Only include files with one of these extensions, like "mp4" or ".mp4"
+	pub extensions: Vec<String>,
+}
+
+#[derive(Serialize, Type, Debug, Clone, PartialEq)]
+pub struct TopEntry {
+	pub path: String,
+	/// Bytes added or updated
+	#[specta(type = u32)] // tauri bigint fix
+	pub size: u64,
+	/// Number of changed files counted in `size`
+	pub count: u32,
+	/// The change of a file, `None` for directories
+	pub kind: Option<ChangeKind>,
+}
+
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct TopItems {
+	pub files: Vec<TopEntry>,
+	/// Directories by the size of the files directly in them
+	pub dirs_by_own_size: Vec<TopEntry>,
+	/// Directories by the size of everything below them
+	pub dirs_by_subtree_size: Vec<TopEntry>,
+}
+
+fn has_extension(path: &str, extensions: &[String]) -> bool {
+	if extensions.is_empty() {
+		return true;
+	}
+	let extension = match Path::new(path).extension() {
+		Some(extension) => extension.to_string_lossy(),
+		None => return false,
+	};
+	extensions
+		.iter()
+		.any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&extension))
+}
+
+fn largest(mut entries: Vec<TopEntry>, limit: usize) -> Vec<TopEntry> {
+	entries.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+	entries.truncate(limit);
+	entries
+}
+
+/// The `limit` largest changed files and directories below `root`, which is
+/// normally the backup path. Only files that pass `filter` count towards
+/// directory sizes, and removed files are left out since the backup stores
+/// nothing for them.
+pub fn top(dir_map: &DirMap, root: &str, filter: &TopFilter, limit: usize) -> TopItems {
+	let root = match &filter.prefix {
+		Some(prefix) if is_at_or_below(prefix, root) => prefix.as_str(),
+		Some(_) => {
+			return TopItems {
+				files: Vec::new(),
+				dirs_by_own_size: Vec::new(),
+				dirs_by_subtree_size: Vec::new(),
+			}
+		}
+		None => root,
+	};
+	let root = root.trim_end_matches('/');
+	let mut files = Vec::new();
+	let mut own: HashMap<&str, (u64, u32)> = HashMap::new();
+	let mut subtree: HashMap<&str, (u64, u32)> = HashMap::new();
+
+	for (dir, contents) in &dir_map.map {
+		if !is_at_or_below(dir, root) {
+			continue;
+		}
+		for (name, item) in contents {
+			let kind = match &item.change {
+				Some(change) if change.kind != ChangeKind::Removed => change.kind,
+				_ => continue,
+			};
+			let path = join_path(dir, name);
+			if !has_extension(&path, &filter.extensions) {
+				continue;
+			}
+			let own_entry = own.entry(dir.as_str()).or_default();
+			own_entry.0 += item.size;
+			own_entry.1 += 1;
+			for ancestor in Path::new(dir.as_str()).ancestors() {
+				let ancestor = ancestor.to_str().unwrap_or_default();
+				if !is_at_or_below(ancestor, root) {
+					break;
+				}
+				let subtree_entry = subtree.entry(ancestor).or_default();
+				subtree_entry.0 += item.size;
+				subtree_entry.1 += 1;
+			}
+			files.push(TopEntry {
+				path,
+				size: item.size,
+				count: 1,
+				kind: Some(kind),
+			});
+		}
+	}
+
+	let dir_entries = |sizes: HashMap<&str, (u64, u32)>| {
+		let entries = sizes
+			.into_iter()
+			.map(|(path, (size, count))| TopEntry {
+				path: path.to_string(),
+				size,
+				count,
+				kind: None,
+			})
+			.collect();
+		largest(entries, limit)
+	};
+	TopItems {
+		files: largest(files, limit),
+		dirs_by_own_size: dir_entries(own),
+		dirs_by_subtree_size: dir_entries(subtree),
+	}
+}
+
+#[test]
+fn test_top() {
+	use crate::test_util::{add, delete};
+
+	let mut dir_map = DirMap::new();
+	dir_map.add_change(add("/b/2/a.mp4", 50)).unwrap();
+	dir_map.add_change(add("/b/2/dir/b.MP4", 30)).unwrap();
+	dir_map.add_change(add("/b/2/dir/c.txt", 40)).unwrap();
+	dir_map.add_change(add("/b/2/dir/sub/d.txt", 100)).unwrap();
+	dir_map.add_change(add("/b/20/e.txt", 1000)).unwrap();
+	dir_map.add_change(delete("/b/2/dir/old.mp4", 500)).unwrap();
+
+	let paths = |entries: &[TopEntry]| -> Vec<(String, u64)> {
+		entries.iter().map(|e| (e.path.clone(), e.size)).collect()
+	};
+	let top_items = top(&dir_map, "/b/2", &TopFilter::default(), 2);
+	assert_eq!(
+		paths(&top_items.files),
+		[
+			("/b/2/dir/sub/d.txt".into(), 100),
+			("/b/2/a.mp4".into(), 50)
+		]
+	);
+	assert_eq!(
+		paths(&top_items.dirs_by_own_size),
+		[("/b/2/dir/sub".into(), 100), ("/b/2/dir".into(), 70)]
+	);
+	assert_eq!(
+		paths(&top_items.dirs_by_subtree_size),
+		[("/b/2".into(), 220), ("/b/2/dir".into(), 170)]
+	);
+	assert_eq!(top_items.dirs_by_subtree_size[0].count, 4);
+
+	let filter = TopFilter {
+		prefix: Some("/b/2/dir".into()),
+		extensions: vec![".mp4".into()],
+	};
+	let top_items = top(&dir_map, "/b/2", &filter, 10);
+	assert_eq!(paths(&top_items.files), [("/b/2/dir/b.MP4".into(), 30)]);
+	assert_eq!(
+		paths(&top_items.dirs_by_subtree_size),
+		[("/b/2/dir".into(), 30)]
+	);
+
+	let filter = TopFilter {
+		prefix: Some("/b/20".into()),
+		extensions: Vec::new(),
+	};
+	assert!(top(&dir_map, "/b/2", &filter, 10).files.is_empty());
+}
@@ -1,6 +1,9 @@
 // utils.ts
 import * as c from '../../bindings'
 
+/** Errors that have already been shown to the user, or don't need to be */
+const silentErrors: c.ErrorKind['kind'][] = ['Cancelled', 'FullDiskAccess']
+
 export default new Proxy({} as typeof c, {
 	get:
 		(_, property: string) =>
@@ -9,7 +12,10 @@ export default new Proxy({} as typeof c, {
 				// eslint-disable-next-line @typescript-eslint/no-explicit-any
 				return await (c as any)[property](...args)
 			} catch (e) {
-				c.errorPopup(String(e))
+				const error = e as c.Error
+				if (!silentErrors.includes(error.kind)) {
+					c.errorPopup(error.message ?? String(e))
+				}
 				throw e
 			}
 		},
@@ -1,6 +1,14 @@
 import { get, writable } from 'svelte/store'
+import { listen } from '@tauri-apps/api/event'
 import commands from '../lib/commands'
-import type { Backup } from '../../bindings'
+import type {
+	Backup,
+	BackupSize,
+	CompareProgress,
+	ComparisonExclusions,
+	ItemChange,
+	Totals,
+} from '../../bindings'
 
 export const backups = (() => {
 	const store = writable<Backup[] | null>(null)
@@ -17,6 +25,7 @@ export type BackupInfo = {
 	old: string
 	new: string
 	loading: boolean
+	progress: CompareProgress | null
 }
 export const backupInfos = (() => {
 	const store = writable([] as BackupInfo[])
@@ -31,6 +40,9 @@ export const backupInfos = (() => {
 					if ($page.loading !== info.loading) {
 						page.set_loading(info.loading)
 					}
+					if (info.progress) {
+						compareProgress.set(info.progress)
+					}
 				}
 			}
 			store.set(result)
@@ -38,8 +50,49 @@ export const backupInfos = (() => {
 	}
 })()
 
+export const compareProgress = writable(null as CompareProgress | null)
+listen<CompareProgress>('compare-progress', (event) => {
+	if (event.payload.new === get(page).backup?.path) {
+		compareProgress.set(event.payload)
+	}
+})
+
+/** Estimated backup sizes by backup path */
+export const backupSizes = (() => {
+	const store = writable({} as { [path: string]: Totals })
+	let destinationId: string | null = null
+	listen<BackupSize>('backup-size', (event) => {
+		if (event.payload.destination_id === destinationId) {
+			store.update(($sizes) => {
+				$sizes[event.payload.new] = event.payload.totals
+				return $sizes
+			})
+		}
+	})
+	return {
+		subscribe: store.subscribe,
+		/** Loads the sizes that have already been estimated */
+		load: async (id: string | null) => {
+			destinationId = id
+			store.set({})
+			if (id !== null) {
+				const sizes = await commands.backupSizes(id)
+				store.update(($sizes) => {
+					for (const size of sizes) {
+						$sizes[size.new] = size.totals
+					}
+					return $sizes
+				})
+			}
+		},
+	}
+})()
+
 export const selectedPath = writable(null as string | null)
 
+/** Time Machine exclusions, and the excluded paths of the open comparison */
+export const comparisonExclusions = writable(null as ComparisonExclusions | null)
+
 export type PageMap = {
 	[name: string]: PageItems
 }
@@ -48,11 +101,48 @@ export type PageItems = {
 }
 export type PageItem = {
 	size: number
+	removed_size: number
+	change: ItemChange | null
 	isOpen?: boolean
 }
 
 export const pageMap = writable({} as PageMap)
 
+/** Opens the given directories in the tree */
+export function openDirs(paths: string[]) {
+	pageMap.update(($pageMap) => {
+		for (const path of paths) {
+			const i = path.lastIndexOf('/')
+			const item = $pageMap[path.slice(0, i) || '/']?.[path.slice(i + 1)]
+			if (item) {
+				item.isOpen = true
+			}
+		}
+		return $pageMap
+	})
+}
+
+/** Opens the directories above `path` in the tree and selects it */
+export function revealPath(backupPath: string, path: string) {
+	if (!path.startsWith(backupPath + '/')) {
+		return
+	}
+	pageMap.update(($pageMap) => {
+		let dir = backupPath
+		const names = path.slice(backupPath.length + 1).split('/')
+		for (const name of names.slice(0, -1)) {
+			const item = $pageMap[dir]?.[name]
+			if (!item) {
+				break
+			}
+			item.isOpen = true
+			dir = dir + '/' + name
+		}
+		return $pageMap
+	})
+	selectedPath.set(path)
+}
+
 type Page = {
 	backup: Backup | null
 	loading: boolean
@@ -66,6 +156,8 @@ export const page = (() => {
 		subscribe: store.subscribe,
 		set: (value: Page) => {
 			store.set(value)
+			compareProgress.set(null)
+			comparisonExclusions.set(null)
 			backupInfos.load()
 		},
 		set_loading: (value: boolean) => {